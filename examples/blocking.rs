@@ -88,8 +88,8 @@ fn run() -> Result<(), pa::Error> {
     // Now start the main read/write loop! In this example, we pass the input buffer directly to
     // the output buffer, so watch out for feedback.
     'stream: loop {
-        // How many frames are available on the input stream?
-        let in_frames = wait_for_stream(|| stream.read_available(), "Read");
+        // How many frames are available on the input stream? We can read at most `FRAMES` at once.
+        let in_frames = wait_for_stream(|| stream.read_available(), "Read").min(FRAMES);
 
         // If there are frames available, let's take them and add them to our buffer.
         if in_frames > 0 {
@@ -98,8 +98,8 @@ fn run() -> Result<(), pa::Error> {
             println!("Read {:?} frames from the input stream.", in_frames);
         }
 
-        // How many frames are available for writing on the output stream?
-        let out_frames = wait_for_stream(|| stream.write_available(), "Write");
+        // How many frames are available for writing on the output stream? Again, at most `FRAMES`.
+        let out_frames = wait_for_stream(|| stream.write_available(), "Write").min(FRAMES);
 
         // How many frames do we have so far?
         let buffer_frames = (buffer.len() / CHANNELS as usize) as u32;
@@ -0,0 +1,26 @@
+//! Prints a JSON report of the audio setup of the machine, suitable for attaching to bug reports.
+//!
+//! Requires the `serde` feature: `cargo run --example probe --features serde`.
+
+extern crate portaudio;
+extern crate serde_json;
+use portaudio as pa;
+
+fn main() {
+    match run() {
+        Ok(_) => {}
+        e => {
+            eprintln!("Example failed with the following: {:?}", e);
+        }
+    }
+}
+
+fn run() -> Result<(), pa::Error> {
+    let pa = pa::PortAudio::new()?;
+    let report = pa.probe()?;
+    match serde_json::to_string_pretty(&report) {
+        Ok(json) => println!("{}", json),
+        Err(err) => eprintln!("Failed to serialize the report: {}", err),
+    }
+    Ok(())
+}
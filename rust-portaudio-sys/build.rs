@@ -19,6 +19,7 @@
 // IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+extern crate cc;
 extern crate pkg_config;
 
 use std::env;
@@ -33,9 +34,19 @@ fn main() {
     println!("cargo:rerun-if-changed=build.rs");
 
     println!("cargo:rerun-if-env-changed=PORTAUDIO_ONLY_STATIC");
+    println!("cargo:rerun-if-env-changed=PORTAUDIO_FORCE_STATIC");
+    println!("cargo:rerun-if-env-changed=PORTAUDIO_HOST_APIS");
+    println!("cargo:rerun-if-env-changed=PORTAUDIO_LINK_ATOMIC");
     if env::var("PORTAUDIO_ONLY_STATIC").is_err() {
-        // If pkg-config finds a library on the system, we are done
-        if pkg_config::Config::new().atleast_version("19").find("portaudio-2.0").is_ok() {
+        if env::var("PORTAUDIO_FORCE_STATIC").is_ok() {
+            // Only a static archive on the system will do, otherwise build from source
+            if let Some(library) = link_static_system_library() {
+                abi_probe::check(&library.include_paths);
+                return;
+            }
+        } else if let Ok(library) = pkg_config::Config::new().atleast_version("19").find("portaudio-2.0") {
+            // If pkg-config finds a library on the system, we are done
+            abi_probe::check(&library.include_paths);
             return;
         }
     }
@@ -48,15 +59,82 @@ fn build() {
     let out_dir_str = env::var("OUT_DIR").unwrap();
     let out_dir = Path::new(&out_dir_str);
 
+    // The host APIs that the existing build was configured with (if any), so that changing
+    // `PORTAUDIO_HOST_APIS` triggers a rebuild.
+    let host_apis_stamp = out_dir.join("host_apis");
+    let host_apis = env::var("PORTAUDIO_HOST_APIS").unwrap_or_default();
+    let is_stale = ::std::fs::read_to_string(&host_apis_stamp).ok() != Some(host_apis.clone());
+
     let static_lib = out_dir.join("lib/libportaudio.a");
-    if let Err(_) = ::std::fs::metadata(static_lib) {
+    if ::std::fs::metadata(static_lib).is_err() || is_stale {
         platform::download();
         platform::build(out_dir);
+        err_to_panic(::std::fs::write(&host_apis_stamp, host_apis));
     }
 
     platform::print_libs(out_dir);
 }
 
+// Statically link the system's `libportaudio.a`, if pkg-config knows of PortAudio and the archive
+// is installed alongside it.
+//
+// pkg-config's own static linking is not used, as it links libraries within system directories
+// such as `/usr/lib` dynamically regardless.
+fn link_static_system_library() -> Option<pkg_config::Library> {
+    let library = pkg_config::Config::new()
+        .atleast_version("19")
+        .statik(true)
+        .cargo_metadata(false)
+        .find("portaudio-2.0")
+        .ok()?;
+    let mut dirs = library.link_paths.clone();
+    if let Ok(libdir) = pkg_config::get_variable("portaudio-2.0", "libdir") {
+        dirs.push(libdir.into());
+    }
+    let dir = dirs.iter().find(|dir| dir.join("libportaudio.a").exists())?;
+
+    println!("cargo:rustc-link-search=native={}", dir.display());
+    println!("cargo:rustc-link-lib=static=portaudio");
+    // The libraries that PortAudio itself depends upon, e.g. `asound` and `pthread`
+    for path in &library.link_paths {
+        println!("cargo:rustc-link-search=native={}", path.display());
+    }
+    for lib in library.libs.iter().filter(|lib| *lib != "portaudio") {
+        println!("cargo:rustc-link-lib={}", lib);
+    }
+    for framework in &library.frameworks {
+        println!("cargo:rustc-link-lib=framework={}", framework);
+    }
+    link_atomic_if_needed();
+    Some(library)
+}
+
+// Whether a static PortAudio must be linked against `libatomic` for the target.
+//
+// GCC lowers the atomic operations used by PortAudio's lock-free ring buffer to calls into
+// `libatomic` on 32-bit targets without native support for every operation width, such as ARM
+// (e.g. `armv7-unknown-linux-gnueabihf`), MIPS and PowerPC. The `PORTAUDIO_LINK_ATOMIC`
+// environment variable (`1` or `0`) overrides the detection.
+#[allow(dead_code)]
+fn needs_libatomic() -> bool {
+    if let Ok(link) = env::var("PORTAUDIO_LINK_ATOMIC") {
+        return link != "0";
+    }
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let width = env::var("CARGO_CFG_TARGET_POINTER_WIDTH").unwrap_or_default();
+    let arches = ["arm", "mips", "mips32r6", "powerpc", "sparc", "riscv32"];
+    os != "macos" && width == "32" && arches.contains(&&arch[..])
+}
+
+// Link `libatomic` if a static PortAudio requires it for the target (see `needs_libatomic`).
+#[allow(dead_code)]
+fn link_atomic_if_needed() {
+    if needs_libatomic() {
+        println!("cargo:rustc-link-lib=atomic");
+    }
+}
+
 // Similar to unwrap, but panics on just the error value
 #[allow(dead_code)]
 fn err_to_panic<T, E: Display>(result: Result<T, E>) -> T {
@@ -66,6 +144,49 @@ fn err_to_panic<T, E: Display>(result: Result<T, E>) -> T {
     }
 }
 
+// The host APIs requested via the comma separated `PORTAUDIO_HOST_APIS` environment variable, or
+// `None` if PortAudio's own defaults should be used.
+//
+// Each requested API must be one of the given `available` APIs for the target platform.
+#[allow(dead_code)]
+fn requested_host_apis(available: &[&str]) -> Option<Vec<String>> {
+    let apis = match env::var("PORTAUDIO_HOST_APIS") {
+        Ok(apis) => apis,
+        Err(_) => return None,
+    };
+    let apis: Vec<String> = apis.split(',')
+        .map(|api| api.trim().to_lowercase())
+        .filter(|api| !api.is_empty())
+        .collect();
+    for api in &apis {
+        if !available.contains(&&api[..]) {
+            panic!("Unknown host API `{}` in PORTAUDIO_HOST_APIS, expected one of: {}",
+                   api, available.join(", "));
+        }
+    }
+    Some(apis)
+}
+
+// The additional C compiler flags requested via the `native-optimize` feature.
+//
+// `-march=native` tunes the code for the build machine, so it is only used when that is also the
+// machine being built for.
+#[allow(dead_code)]
+fn optimize_cflags() -> Vec<&'static str> {
+    if env::var("CARGO_FEATURE_NATIVE_OPTIMIZE").is_err() {
+        return vec![];
+    }
+    if env::var("CARGO_CFG_TARGET_ENV").map(|env| env == "msvc").unwrap_or(false) {
+        // MSVC has no equivalent of `-march=native`
+        return vec!["/O2"];
+    }
+    let mut flags = vec!["-O3"];
+    if env::var("TARGET").ok() == env::var("HOST").ok() {
+        flags.push("-march=native");
+    }
+    flags
+}
+
 fn run(command: &mut Command) {
     let string = format!("{:?}", command);
     let status = err_to_panic(command.status());
@@ -74,6 +195,96 @@ fn run(command: &mut Command) {
     }
 }
 
+// Verifies that a system PortAudio library matches the struct layouts assumed by our FFI
+// declarations by compiling a tiny C probe against its headers. This catches ABI skew at build time
+// rather than as memory corruption at runtime.
+mod abi_probe {
+    use std::cmp;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::err_to_panic;
+
+    const PROBE_SOURCE: &'static str = "#include <portaudio.h>
+#define PA_ABI_CHECK(name, condition) typedef char name[(condition) ? 1 : -1]
+PA_ABI_CHECK(size_of_PaStreamParameters_does_not_match, sizeof(PaStreamParameters) == EXPECTED_STREAM_PARAMETERS_SIZE);
+PA_ABI_CHECK(size_of_PaDeviceInfo_does_not_match, sizeof(PaDeviceInfo) == EXPECTED_DEVICE_INFO_SIZE);
+";
+
+    pub fn check(include_paths: &[PathBuf]) {
+        let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+        let probe = out_dir.join("abi_probe.c");
+        err_to_panic(fs::write(&probe, PROBE_SOURCE));
+
+        // (size, alignment) of the C types used by the probed structs on the target.
+        let pointer_size: usize = env::var("CARGO_CFG_TARGET_POINTER_WIDTH").unwrap().parse::<usize>().unwrap() / 8;
+        let is_windows = env::var("CARGO_CFG_TARGET_OS").unwrap() == "windows";
+        let is_x86 = env::var("CARGO_CFG_TARGET_ARCH").unwrap() == "x86";
+        let int = (4, 4);
+        let long = if is_windows { int } else { (pointer_size, pointer_size) };
+        // Doubles are only 4-byte aligned within structs on 32-bit x86 System V targets.
+        let double = if is_x86 && !is_windows { (8, 4) } else { (8, 8) };
+        let pointer = (pointer_size, pointer_size);
+
+        let stream_parameters_size = struct_size(&[int, int, long, double, pointer]);
+        let device_info_size =
+            struct_size(&[int, pointer, int, int, int, double, double, double, double, double]);
+
+        // The probe must be compiled for the target rather than the host, so that the sizes seen
+        // by the compiler match those computed above when cross-compiling.
+        let compiler = match cc::Build::new().cargo_metadata(false).try_get_compiler() {
+            Ok(compiler) => compiler,
+            // Without a C compiler we cannot check, but that shouldn't prevent building.
+            Err(err) => {
+                println!("cargo:warning=Skipping the PortAudio ABI check: {}", err);
+                return;
+            }
+        };
+        let mut command = compiler.to_command();
+        for path in include_paths {
+            command.arg(format!("-I{}", path.display()));
+        }
+        command.arg(format!("-DEXPECTED_STREAM_PARAMETERS_SIZE={}", stream_parameters_size));
+        command.arg(format!("-DEXPECTED_DEVICE_INFO_SIZE={}", device_info_size));
+        let object = out_dir.join("abi_probe.o");
+        if compiler.is_like_msvc() {
+            command.arg("-c").arg(&probe).arg(format!("-Fo{}", object.display()));
+        } else {
+            command.arg("-c").arg(&probe).arg("-o").arg(&object);
+        }
+
+        let output = match command.output() {
+            Ok(output) => output,
+            // Without a C compiler we cannot check, but that shouldn't prevent building.
+            Err(err) => {
+                println!("cargo:warning=Skipping the PortAudio ABI check: {:?} failed: {}", command, err);
+                return;
+            }
+        };
+        if !output.status.success() {
+            panic!("The system PortAudio library found via pkg-config does not match the ABI \
+                    expected by portaudio_sys (expected sizeof(PaStreamParameters) == {} and \
+                    sizeof(PaDeviceInfo) == {}). Set PORTAUDIO_ONLY_STATIC to build PortAudio \
+                    from source instead.\n{}",
+                   stream_parameters_size, device_info_size,
+                   String::from_utf8_lossy(&output.stderr));
+        }
+    }
+
+    // The size of a C struct with fields of the given (size, alignment)s.
+    fn struct_size(fields: &[(usize, usize)]) -> usize {
+        let round_up = |n: usize, align: usize| (n + align - 1) / align * align;
+        let mut size = 0;
+        let mut struct_align = 1;
+        for &(field_size, field_align) in fields {
+            size = round_up(size, field_align) + field_size;
+            struct_align = cmp::max(struct_align, field_align);
+        }
+        round_up(size, struct_align)
+    }
+}
+
 #[allow(dead_code)]
 mod unix_platform {
     use std::process::Command;
@@ -81,7 +292,11 @@ mod unix_platform {
 
     use std::env;
 
-    use super::{err_to_panic, run};
+    use super::{err_to_panic, link_atomic_if_needed, optimize_cflags, requested_host_apis, run};
+
+    /// The host APIs that may be toggled via the `configure` script's `--with-*`/`--without-*`
+    /// options.
+    pub const HOST_APIS: &'static [&'static str] = &["alsa", "jack", "oss", "asihpi"];
 
     pub const PORTAUDIO_URL: &'static str = "http://www.portaudio.com/archives/pa_stable_v19_20140130.tgz";
     pub const PORTAUDIO_TAR: &'static str = "pa_stable_v19_20140130.tgz";
@@ -103,6 +318,21 @@ mod unix_platform {
         configure.args(&["--disable-shared", "--enable-static"]); // Only build static lib
         configure.args(&["--prefix", out_dir.to_str().unwrap()]); // Install on the outdir
         configure.arg("--with-pic"); // Build position-independent code (required by Rust)
+        // `--with-pic` only reaches the objects built through libtool, which isn't guaranteed on
+        // every platform (e.g. the BSDs), so pass `-fPIC` to the compiler directly as well. This
+        // allows the static library to be linked into a `cdylib`. Setting `CFLAGS` replaces
+        // autoconf's default of `-g -O2`, so that default is kept unless the user set their own.
+        let cflags = env::var("CFLAGS").unwrap_or_else(|_| "-g -O2".to_string());
+        let cflags = format!("{} -fPIC {}", cflags, optimize_cflags().join(" "));
+        configure.env("CFLAGS", cflags.trim());
+
+        // Only build the host APIs requested via `PORTAUDIO_HOST_APIS`
+        if let Some(requested) = requested_host_apis(HOST_APIS) {
+            for api in HOST_APIS {
+                let with = if requested.iter().any(|r| r == api) { "with" } else { "without" };
+                configure.arg(format!("--{}-{}", with, api));
+            }
+        }
 
         // cross platform builds
         let rustc_linker = env::var("RUSTC_LINKER"); // if the linker is configured to be non default it will look like this /usr/bin/arm-linux-gnueabihf-gcc
@@ -137,6 +367,7 @@ mod unix_platform {
     pub fn print_libs(out_dir: &Path) {
         let out_str = out_dir.to_str().unwrap();
         println!("cargo:rustc-flags=-L native={}/lib -l static=portaudio", out_str);
+        link_atomic_if_needed();
     }
 }
 
@@ -147,7 +378,7 @@ mod platform {
     use super::unix_platform;
     use std::path::Path;
 
-    use super::{run, err_to_panic};
+    use super::{run, err_to_panic, link_atomic_if_needed};
 
     pub fn download() {
         run(Command::new("wget").arg(unix_platform::PORTAUDIO_URL));
@@ -162,6 +393,7 @@ mod platform {
         let portaudio_pc_file = portaudio_pc_file.to_str().unwrap();
 
         err_to_panic(pkg_config::Config::new().statik(true).find(portaudio_pc_file));
+        link_atomic_if_needed();
     }
 }
 
@@ -173,6 +405,17 @@ mod platform {
 
     extern crate cmake;
 
+    use super::{optimize_cflags, requested_host_apis};
+
+    /// The host APIs that may be toggled, along with their respective PortAudio cmake options.
+    const HOST_APIS: &'static [(&'static str, &'static str)] = &[
+        ("asio", "PA_USE_ASIO"),
+        ("ds", "PA_USE_DS"),
+        ("wmme", "PA_USE_WMME"),
+        ("wasapi", "PA_USE_WASAPI"),
+        ("wdmks", "PA_USE_WDMKS"),
+    ];
+
     pub fn download() {
         let mut command = Command::new("cmake");
 
@@ -194,10 +437,25 @@ mod platform {
         // Note: the 'PA_WDMKS_NO_KSGUID_LIB' preprocessor definition is a
         // workaround for an issue which is fixed in the newer versions. See
         // https://app.assembla.com/spaces/portaudio/subversion/commits/1944
-        cmake::Config::new(source_path)
+        let mut config = cmake::Config::new(source_path);
+        // Only build the host APIs requested via `PORTAUDIO_HOST_APIS`
+        let names: Vec<&str> = HOST_APIS.iter().map(|&(name, _)| name).collect();
+        if let Some(requested) = requested_host_apis(&names) {
+            for &(name, option) in HOST_APIS {
+                let on = if requested.iter().any(|r| r == name) { "ON" } else { "OFF" };
+                config.define(option, on);
+            }
+        }
+        for flag in optimize_cflags() {
+            config.cflag(flag);
+        }
+        config
             // .generator("MSYS Makefiles")
             .define("CMAKE_ARCHIVE_OUTPUT_DIRECTORY_DEBUG", out_dir)
             .define("CMAKE_ARCHIVE_OUTPUT_DIRECTORY_RELEASE", out_dir)
+            // Build position-independent code so that the static library may be linked into a
+            // `cdylib`, matching `--with-pic` on unix.
+            .define("CMAKE_POSITION_INDEPENDENT_CODE", "ON")
             .cflag("-DPA_WDMKS_NO_KSGUID_LIB")
             .out_dir(out_dir)
             .build_target("portaudio_static")
@@ -12,8 +12,20 @@ mod c_library {
 }
 
 mod portaudio;
+#[cfg(windows)]
+mod pa_win_wmme;
+#[cfg(windows)]
+mod pa_win_wasapi;
+#[cfg(target_os = "macos")]
+mod pa_mac_core;
 
 pub use portaudio::*;
+#[cfg(windows)]
+pub use pa_win_wmme::*;
+#[cfg(windows)]
+pub use pa_win_wasapi::*;
+#[cfg(target_os = "macos")]
+pub use pa_mac_core::*;
 
 pub const PA_NO_DEVICE : PaDeviceIndex = -1;
 
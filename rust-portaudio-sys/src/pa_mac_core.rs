@@ -0,0 +1,34 @@
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+
+//! Declarations from the CoreAudio specific `pa_mac_core.h` header.
+
+use portaudio::PaHostApiTypeId;
+
+/** Allows PortAudio to change things like the device's frame size, which allows for much lower
+ latency, but might disrupt the device if other programs are using it, even when you are just
+ querying the device.
+*/
+pub const paMacCoreChangeDeviceParameters: ::std::os::raw::c_ulong = 0x01;
+/** In combination with the above flag, causes the stream opening to fail, unless the exact sample
+ rates are supported by the device.
+*/
+pub const paMacCoreFailIfConversionRequired: ::std::os::raw::c_ulong = 0x02;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PaMacCoreStreamInfo {
+    /** sizeof(PaMacCoreStreamInfo) */
+    pub size: ::std::os::raw::c_ulong,
+    /** paCoreAudio */
+    pub hostApiType: PaHostApiTypeId,
+    /** 0x01 */
+    pub version: ::std::os::raw::c_ulong,
+    /** flags to modify behaviour */
+    pub flags: ::std::os::raw::c_ulong,
+    /** channel map for the device */
+    pub channelMap: *const i32,
+    /** size of the channel map */
+    pub channelMapSize: ::std::os::raw::c_ulong,
+}
@@ -0,0 +1,69 @@
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+
+//! Declarations from the WASAPI specific `pa_win_wasapi.h` header.
+
+use pa_win_wmme::PaWinWaveFormatChannelMask;
+use portaudio::PaHostApiTypeId;
+
+pub type PaWasapiFlags = ::std::os::raw::c_ulong;
+/** Put WASAPI into exclusive mode. */
+pub const paWinWasapiExclusive: PaWasapiFlags = 1;
+/** Allow to skip internal PA processing completely. */
+pub const paWinWasapiRedirectHostProcessor: PaWasapiFlags = 2;
+/** Assign custom channel mask. */
+pub const paWinWasapiUseChannelMask: PaWasapiFlags = 4;
+/** Select non-Event driven method of data read/write. */
+pub const paWinWasapiPolling: PaWasapiFlags = 8;
+/** Force custom thread priority setting. */
+pub const paWinWasapiThreadPriority: PaWasapiFlags = 16;
+/** Force explicit sample format and do not allow PA to select suitable working format. */
+pub const paWinWasapiExplicitSampleFormat: PaWasapiFlags = 32;
+/** Allow the WASAPI backend to convert the sample format and rate in shared mode. */
+pub const paWinWasapiAutoConvert: PaWasapiFlags = 64;
+
+pub type PaWasapiHostProcessorCallback = ::std::option::Option<
+    unsafe extern "C" fn(
+        inputBuffer: *mut ::std::os::raw::c_void,
+        inputFrames: ::std::os::raw::c_long,
+        outputBuffer: *mut ::std::os::raw::c_void,
+        outputFrames: ::std::os::raw::c_long,
+        userData: *mut ::std::os::raw::c_void,
+    ),
+>;
+
+pub type PaWasapiThreadPriority = ::std::os::raw::c_int;
+pub const eThreadPriorityNone: PaWasapiThreadPriority = 0;
+
+pub type PaWasapiStreamCategory = ::std::os::raw::c_int;
+pub const eAudioCategoryOther: PaWasapiStreamCategory = 0;
+
+pub type PaWasapiStreamOption = ::std::os::raw::c_int;
+pub const eStreamOptionNone: PaWasapiStreamOption = 0;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PaWasapiStreamInfo {
+    /** sizeof(PaWasapiStreamInfo) */
+    pub size: ::std::os::raw::c_ulong,
+    /** paWASAPI */
+    pub hostApiType: PaHostApiTypeId,
+    /** 1 */
+    pub version: ::std::os::raw::c_ulong,
+    /** collection of PaWasapiFlags */
+    pub flags: ::std::os::raw::c_ulong,
+    /** Support for WAVEFORMATEXTENSIBLE channel masks. Only valid when the
+     paWinWasapiUseChannelMask flag is set.
+    */
+    pub channelMask: PaWinWaveFormatChannelMask,
+    /** Only valid when the paWinWasapiRedirectHostProcessor flag is set. */
+    pub hostProcessorOutput: PaWasapiHostProcessorCallback,
+    pub hostProcessorInput: PaWasapiHostProcessorCallback,
+    /** Only valid when the paWinWasapiThreadPriority flag is set. */
+    pub threadPriority: PaWasapiThreadPriority,
+    /** Stream category. */
+    pub streamCategory: PaWasapiStreamCategory,
+    /** Stream option. */
+    pub streamOption: PaWasapiStreamOption,
+}
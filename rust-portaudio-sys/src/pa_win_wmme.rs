@@ -0,0 +1,54 @@
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+
+//! Declarations from the WinMME specific `pa_win_wmme.h` header.
+
+use portaudio::{PaDeviceIndex, PaHostApiTypeId};
+
+pub const paWinMmeUseLowLevelLatencyParameters: ::std::os::raw::c_ulong = 0x01;
+/** Use the WinMME specific multiple device feature. */
+pub const paWinMmeUseMultipleDevices: ::std::os::raw::c_ulong = 0x02;
+pub const paWinMmeUseChannelMask: ::std::os::raw::c_ulong = 0x04;
+/** By default, the MME implementation drops the processing thread's priority to
+ THREAD_PRIORITY_NORMAL and sleeps the thread if the CPU load exceeds 100%.
+ This flag disables any priority throttling.
+*/
+pub const paWinMmeDontThrottleOverloadedProcessingThread: ::std::os::raw::c_ulong = 0x08;
+pub const paWinMmeWaveFormatDolbyAc3Spdif: ::std::os::raw::c_ulong = 0x10;
+pub const paWinMmeWaveFormatWmaSpdif: ::std::os::raw::c_ulong = 0x20;
+
+pub type PaWinWaveFormatChannelMask = ::std::os::raw::c_ulong;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PaWinMmeDeviceAndChannelCount {
+    pub device: PaDeviceIndex,
+    pub channelCount: ::std::os::raw::c_int,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PaWinMmeStreamInfo {
+    /** sizeof(PaWinMmeStreamInfo) */
+    pub size: ::std::os::raw::c_ulong,
+    /** paMME */
+    pub hostApiType: PaHostApiTypeId,
+    /** 1 */
+    pub version: ::std::os::raw::c_ulong,
+    pub flags: ::std::os::raw::c_ulong,
+    /** Low-level latency setting support. Only valid when the
+     paWinMmeUseLowLevelLatencyParameters flag is set.
+    */
+    pub framesPerBuffer: ::std::os::raw::c_ulong,
+    pub bufferCount: ::std::os::raw::c_ulong,
+    /** Multiple devices per direction support. Only valid when the
+     paWinMmeUseMultipleDevices flag is set.
+    */
+    pub devices: *mut PaWinMmeDeviceAndChannelCount,
+    pub deviceCount: ::std::os::raw::c_ulong,
+    /** Support for WAVEFORMATEXTENSIBLE channel masks. Only valid when the
+     paWinMmeUseChannelMask flag is set.
+    */
+    pub channelMask: PaWinWaveFormatChannelMask,
+}
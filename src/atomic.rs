@@ -0,0 +1,35 @@
+//! Lock-free counters that also build on targets without 64-bit atomics.
+
+use std::sync::atomic::Ordering;
+
+#[cfg(target_has_atomic = "64")]
+type Inner = std::sync::atomic::AtomicU64;
+#[cfg(not(target_has_atomic = "64"))]
+type Inner = std::sync::atomic::AtomicUsize;
+
+/// A `u64` count that may be updated from the real-time audio thread.
+///
+/// Uses `AtomicU64` where the target supports it. Elsewhere (e.g. 32-bit ARM and MIPS) the count
+/// is held in an `AtomicUsize` rather than falling back to a lock, so it wraps at `u32::MAX`.
+#[derive(Debug, Default)]
+pub struct AtomicCounter(Inner);
+
+// The casts are only necessary on targets without 64-bit atomics.
+#[allow(clippy::unnecessary_cast)]
+impl AtomicCounter {
+    pub fn new(value: u64) -> Self {
+        AtomicCounter(Inner::new(value as _))
+    }
+
+    pub fn load(&self, order: Ordering) -> u64 {
+        self.0.load(order) as u64
+    }
+
+    pub fn store(&self, value: u64, order: Ordering) {
+        self.0.store(value as _, order)
+    }
+
+    pub fn fetch_add(&self, value: u64, order: Ordering) -> u64 {
+        self.0.fetch_add(value as _, order) as u64
+    }
+}
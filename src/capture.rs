@@ -0,0 +1,354 @@
+//! Input streams whose audio is collected for another thread, e.g. an encoder or analyser.
+//!
+//! A capture started via
+//! [**PortAudio::open_capture**](../struct.PortAudio.html#method.open_capture) reads a blocking
+//! **Input** stream on a dedicated worker thread, which appends each chunk to a fixed-size ring
+//! buffer. The audio is taken from the ring via the returned
+//! [**CaptureReader**](./struct.CaptureReader.html), which never blocks.
+//!
+//! When the reader falls behind and the ring fills up, the capture's
+//! [**OverflowPolicy**](./enum.OverflowPolicy.html) decides which audio is lost. Frames that are
+//! dropped are counted and may be retrieved via **CaptureReader::dropped_frames**.
+//!
+//! The worker reads each chunk into the stream's own buffer and the ring's storage is reserved up
+//! front, so capturing does not allocate once started. If reading the stream fails, the worker
+//! stops and the error is returned by the next **CaptureReader::read**.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use super::error::Error;
+use super::stream::{Blocking, Buffer, Input, InputSettings, Stream};
+use super::{Life, Sample};
+
+/// What to do with captured audio when the ring buffer is full.
+///
+/// Defaults to **OverflowPolicy::DropOldest**.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OverflowPolicy {
+    /// Discard the oldest frames in the ring to make room for the new ones. The reader always
+    /// receives the most recent audio, which suits real-time monitoring and metering.
+    #[default]
+    DropOldest,
+    /// Discard the new frames that do not fit. The reader receives a gap-free signal up to the
+    /// point at which the ring filled up.
+    DropNewest,
+    /// Wait for the reader to make room, applying backpressure to the capture. Nothing is dropped
+    /// from the ring, however while the worker waits it is not reading the stream, so PortAudio
+    /// may discard input instead (see **CaptureReader::input_overflows**).
+    ///
+    /// Waiting is only acceptable because the stream is read on the capture's own worker thread
+    /// rather than within a real-time callback.
+    Block,
+}
+
+/// The captured samples that have not yet been taken by the reader.
+struct Ring<T> {
+    samples: VecDeque<T>,
+    /// The maximum number of samples held by the ring.
+    capacity: usize,
+    /// The number of frames discarded due to the ring being full.
+    dropped_frames: u64,
+    /// The number of reads for which PortAudio reported that input was discarded.
+    input_overflows: u64,
+    /// The error that stopped the worker, if it has not yet been returned by a read.
+    error: Option<Error>,
+}
+
+/// The state shared between the worker thread and the **CaptureReader**.
+struct Shared<T> {
+    ring: Mutex<Ring<T>>,
+    /// Notified when the reader makes room in the ring, or when the capture is stopped.
+    space: Condvar,
+    stop: AtomicBool,
+    policy: OverflowPolicy,
+    channels: usize,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, Ring<T>> {
+        match self.ring.lock() {
+            Ok(ring) => ring,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl<T: Copy> Shared<T> {
+    /// Append a chunk of whole frames to the ring according to the capture's **OverflowPolicy**.
+    fn push(&self, chunk: &[T]) {
+        let mut ring = self.lock();
+        let channels = self.channels;
+        match self.policy {
+            OverflowPolicy::DropOldest => {
+                // Of a chunk larger than the whole ring, only its end is kept.
+                let skipped = chunk.len().saturating_sub(ring.capacity);
+                let chunk = &chunk[skipped..];
+                let excess = (ring.samples.len() + chunk.len()).saturating_sub(ring.capacity);
+                ring.samples.drain(..excess);
+                ring.samples.extend(chunk);
+                ring.dropped_frames += ((skipped + excess) / channels) as u64;
+            }
+            OverflowPolicy::DropNewest => {
+                let free = ring.capacity - ring.samples.len();
+                let count = std::cmp::min(free, chunk.len());
+                ring.samples.extend(&chunk[..count]);
+                ring.dropped_frames += ((chunk.len() - count) / channels) as u64;
+            }
+            OverflowPolicy::Block => {
+                let mut rest = chunk;
+                while !rest.is_empty() {
+                    while ring.samples.len() == ring.capacity && !self.stop.load(Ordering::SeqCst) {
+                        ring = match self.space.wait(ring) {
+                            Ok(ring) => ring,
+                            Err(poisoned) => poisoned.into_inner(),
+                        };
+                    }
+                    if self.stop.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    let free = ring.capacity - ring.samples.len();
+                    let count = std::cmp::min(free, rest.len());
+                    ring.samples.extend(&rest[..count]);
+                    rest = &rest[count..];
+                }
+            }
+        }
+    }
+
+    /// Move as many whole frames from the ring into the given `buffer` as are available and fit,
+    /// returning the number of samples moved.
+    fn pop(&self, buffer: &mut [T]) -> usize {
+        let mut ring = self.lock();
+        let count = std::cmp::min(
+            ring.samples.len(),
+            buffer.len() / self.channels * self.channels,
+        );
+        for (sample, captured) in buffer.iter_mut().zip(ring.samples.drain(..count)) {
+            *sample = captured;
+        }
+        drop(ring);
+        self.space.notify_one();
+        count
+    }
+}
+
+/// A handle for taking the interleaved audio captured by
+/// [**PortAudio::open_capture**](../struct.PortAudio.html#method.open_capture).
+///
+/// The reader owns the capture's worker thread and stream. Dropping it (or calling
+/// **CaptureReader::stop**) stops the stream and joins the worker.
+pub struct CaptureReader<T> {
+    shared: Arc<Shared<T>>,
+    worker: Option<std::thread::JoinHandle<Result<(), Error>>>,
+}
+
+impl<T: Sample> CaptureReader<T> {
+    /// Move as many whole captured frames into the given interleaved `buffer` as are available and
+    /// fit, returning the number of frames moved.
+    ///
+    /// This never blocks. Returns `Ok(0)` if no frames have been captured since the last read.
+    ///
+    /// If reading the stream failed, the capture has stopped and the error is returned once,
+    /// without moving any frames. The frames captured before the failure remain in the ring buffer
+    /// and may be taken by subsequent reads.
+    pub fn read(&mut self, buffer: &mut [T]) -> Result<usize, Error> {
+        if let Some(err) = self.shared.lock().error.take() {
+            return Err(err);
+        }
+        Ok(self.shared.pop(buffer) / self.shared.channels)
+    }
+
+    /// The number of captured frames that are waiting in the ring buffer.
+    pub fn fill_level(&self) -> usize {
+        self.shared.lock().samples.len() / self.shared.channels
+    }
+
+    /// The number of frames that the ring buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.shared.lock().capacity / self.shared.channels
+    }
+
+    /// The policy applied when the ring buffer is full.
+    pub fn policy(&self) -> OverflowPolicy {
+        self.shared.policy
+    }
+
+    /// The number of captured frames that were discarded by the **OverflowPolicy** because the
+    /// ring buffer was full. This is always `0` for **OverflowPolicy::Block**.
+    pub fn dropped_frames(&self) -> u64 {
+        self.shared.lock().dropped_frames
+    }
+
+    /// The number of reads of the stream for which PortAudio reported that input was discarded
+    /// before it could be read, e.g. while the worker was blocked by **OverflowPolicy::Block**.
+    ///
+    /// See **Stream::input_overflow_count**.
+    pub fn input_overflows(&self) -> u64 {
+        self.shared.lock().input_overflows
+    }
+
+    /// Stop the capture, returning the error that stopped the worker thread if it has not already
+    /// been returned by **CaptureReader::read**, or any error encountered stopping the stream.
+    ///
+    /// Any frames remaining in the ring buffer are discarded.
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.stop_worker()
+    }
+}
+
+impl<T> CaptureReader<T> {
+    /// Signal the worker thread to stop and wait for it to finish.
+    fn stop_worker(&mut self) -> Result<(), Error> {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        self.shared.space.notify_all();
+        let stopped = match self.worker.take() {
+            Some(worker) => worker.join().unwrap_or(Err(Error::InternalError)),
+            None => Ok(()),
+        };
+        match self.shared.lock().error.take() {
+            Some(err) => Err(err),
+            None => stopped,
+        }
+    }
+}
+
+impl<T> Drop for CaptureReader<T> {
+    fn drop(&mut self) {
+        let _ = self.stop_worker();
+    }
+}
+
+/// Open and start a blocking **Input** stream that is read on a worker thread into a ring buffer
+/// holding `capacity` frames, returning the **CaptureReader** through which the audio is taken.
+pub fn open<T>(
+    life: Arc<Life>,
+    settings: InputSettings<T>,
+    capacity: usize,
+    policy: OverflowPolicy,
+) -> Result<CaptureReader<T>, Error>
+where
+    T: Sample + Send + 'static,
+{
+    if settings.params.channel_count <= 0 {
+        return Err(Error::InvalidChannelCount);
+    }
+    let chunk_frames = settings.frames_per_buffer;
+    if chunk_frames == 0 || capacity == 0 {
+        return Err(Error::BufferTooSmall);
+    }
+    let channels = settings.params.channel_count as usize;
+    let samples = capacity.checked_mul(channels).ok_or(Error::BufferTooBig)?;
+    let shared = Arc::new(Shared {
+        ring: Mutex::new(Ring {
+            samples: VecDeque::with_capacity(samples),
+            capacity: samples,
+            dropped_frames: 0,
+            input_overflows: 0,
+            error: None,
+        }),
+        space: Condvar::new(),
+        stop: AtomicBool::new(false),
+        policy: policy,
+        channels: channels,
+    });
+    let mut stream = Stream::<Blocking<Buffer>, Input<T>>::open(life, settings)?;
+    stream.start()?;
+    let worker_shared = shared.clone();
+    let builder = std::thread::Builder::new().name("portaudio-capture".to_string());
+    let worker = builder
+        .spawn(move || {
+            let shared = worker_shared;
+            while !shared.stop.load(Ordering::SeqCst) {
+                match stream.read_allowing_overflow(chunk_frames) {
+                    Ok((chunk, _overflowed)) => {
+                        shared.lock().input_overflows = stream.input_overflow_count();
+                        shared.push(chunk);
+                    }
+                    Err(err) => {
+                        shared.lock().error = Some(err);
+                        break;
+                    }
+                }
+            }
+            stream.stop()
+        })
+        .map_err(|_| Error::InsufficientMemory)?;
+    Ok(CaptureReader {
+        shared: shared,
+        worker: Some(worker),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared(capacity: usize, policy: OverflowPolicy) -> Shared<i16> {
+        Shared {
+            ring: Mutex::new(Ring {
+                samples: VecDeque::new(),
+                capacity: capacity,
+                dropped_frames: 0,
+                input_overflows: 0,
+                error: None,
+            }),
+            space: Condvar::new(),
+            stop: AtomicBool::new(false),
+            policy: policy,
+            channels: 2,
+        }
+    }
+
+    #[test]
+    fn overflow_policy_chooses_the_dropped_frames() {
+        let oldest = shared(6, OverflowPolicy::DropOldest);
+        oldest.push(&[1, 2, 3, 4]);
+        oldest.push(&[5, 6, 7, 8]);
+        let mut buffer = [0; 8];
+        assert_eq!(oldest.pop(&mut buffer), 6);
+        assert_eq!(buffer[..6], [3, 4, 5, 6, 7, 8]);
+        oldest.push(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(oldest.lock().dropped_frames, 2);
+
+        let newest = shared(6, OverflowPolicy::DropNewest);
+        newest.push(&[1, 2, 3, 4]);
+        newest.push(&[5, 6, 7, 8]);
+        assert_eq!(newest.pop(&mut buffer), 6);
+        assert_eq!(buffer[..6], [1, 2, 3, 4, 5, 6]);
+        assert_eq!(newest.lock().dropped_frames, 1);
+    }
+
+    #[test]
+    fn block_waits_for_the_reader() {
+        let ring = Arc::new(shared(4, OverflowPolicy::Block));
+        ring.push(&[1, 2, 3, 4]);
+        let writer = ring.clone();
+        let worker = std::thread::spawn(move || writer.push(&[5, 6]));
+        let mut buffer = [0; 2];
+        while ring.pop(&mut buffer) == 0 {}
+        assert_eq!(buffer, [1, 2]);
+        worker.join().unwrap();
+        let mut buffer = [0; 4];
+        assert_eq!(ring.pop(&mut buffer), 4);
+        assert_eq!(buffer, [3, 4, 5, 6]);
+        assert_eq!(ring.lock().dropped_frames, 0);
+    }
+
+    #[test]
+    fn worker_error_is_returned_by_the_next_read() {
+        let shared = shared(4, OverflowPolicy::DropOldest);
+        shared.push(&[1, 2]);
+        shared.lock().error = Some(Error::DeviceUnavailable);
+        let mut reader = CaptureReader {
+            shared: Arc::new(shared),
+            worker: None,
+        };
+        let mut buffer = [0; 4];
+        assert_eq!(reader.read(&mut buffer), Err(Error::DeviceUnavailable));
+        assert_eq!(reader.read(&mut buffer), Ok(1));
+        assert_eq!(buffer[..2], [1, 2]);
+        assert_eq!(reader.stop(), Ok(()));
+    }
+}
@@ -4,6 +4,8 @@
 //!
 
 use ffi;
+#[cfg(target_os = "linux")]
+use std::error::Error as StdError;
 
 enum_from_primitive! {
 /// Error codes returned by PortAudio functions.
@@ -157,3 +159,40 @@ impl ::std::error::Error for Error {
         }
     }
 }
+
+/// Errors returned by **PortAudio::new_with_alsa_config**.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub enum AlsaConfigError {
+    /// The configuration file does not exist, or an inline configuration could not be written.
+    Io(::std::io::Error),
+    /// PortAudio failed to initialize, or its ALSA host API is not available.
+    PortAudio(Error),
+}
+
+#[cfg(target_os = "linux")]
+impl From<Error> for AlsaConfigError {
+    fn from(err: Error) -> Self {
+        AlsaConfigError::PortAudio(err)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ::std::fmt::Display for AlsaConfigError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        match *self {
+            AlsaConfigError::Io(ref err) => write!(f, "ALSA configuration: {}", err),
+            AlsaConfigError::PortAudio(ref err) => ::std::fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl StdError for AlsaConfigError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            AlsaConfigError::Io(ref err) => Some(err),
+            AlsaConfigError::PortAudio(ref err) => Some(err),
+        }
+    }
+}
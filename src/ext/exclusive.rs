@@ -0,0 +1,177 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2013 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Backend-agnostic requests for exclusive access to a device.
+
+#[cfg(any(windows, target_os = "macos"))]
+use ffi;
+#[cfg(any(windows, target_os = "macos"))]
+use std::os::raw;
+use stream::Parameters;
+use types::HostApiTypeId;
+
+/// Host API specific stream info requesting exclusive access to a device, for the lowest latency
+/// and bit-exact output, whichever host API the device belongs to.
+///
+/// Each host API offers exclusive access in a different way, if at all:
+///
+/// | Host API | Behaviour | `is_exclusive` |
+/// |----------|-----------|----------------|
+/// | WASAPI | Opens the stream in exclusive mode via `paWinWasapiExclusive`. The device must support the stream's exact sample format, rate and channel count, otherwise opening fails. | `true` |
+/// | ASIO, WDM-KS | Always exclusive, so no stream info is needed. | `true` |
+/// | CoreAudio | PortAudio does not expose hog mode. Instead `paMacCoreChangeDeviceParameters` and `paMacCoreFailIfConversionRequired` are set, so the device is switched to the stream's sample rate and no sample rate conversion takes place, however other applications may still use the device. | `false` |
+/// | ALSA | PortAudio has no exclusive flag. Opening a `hw:` device rather than `default` or a `dmix` device bypasses the sound server and mixing, but this is a choice of device rather than of stream info. | `false` |
+/// | Others | Shared access only. | `false` |
+///
+/// Where exclusive access is not available the stream is opened in shared mode as normal, so
+/// check **ExclusiveStreamInfo::is_exclusive** to warn the user or to adjust latency expectations.
+///
+/// The info must outlive any use of the **StreamParameters** to which it is attached (see
+/// **ExclusiveStreamInfo::attach**).
+pub struct ExclusiveStreamInfo {
+    host_api: HostApiTypeId,
+    info: Info,
+}
+
+/// The host API specific stream info structure, boxed so that the pointer given to PortAudio
+/// remains valid if the **ExclusiveStreamInfo** is moved.
+enum Info {
+    None,
+    #[cfg(windows)]
+    Wasapi(Box<ffi::PaWasapiStreamInfo>),
+    #[cfg(target_os = "macos")]
+    MacCore(Box<ffi::PaMacCoreStreamInfo>),
+}
+
+impl ExclusiveStreamInfo {
+    /// Construct the stream info requesting exclusive access to a device of the given host API.
+    ///
+    /// See **PortAudio::exclusive_stream_info** to construct it for a particular device.
+    pub fn new(host_api: HostApiTypeId) -> Self {
+        ExclusiveStreamInfo {
+            host_api: host_api,
+            info: Info::for_host_api(host_api),
+        }
+    }
+
+    /// The host API for which the stream info was constructed.
+    pub fn host_api(&self) -> HostApiTypeId {
+        self.host_api
+    }
+
+    /// Whether the stream is opened with exclusive access to the device, rather than falling back
+    /// to shared access. See the table above for each host API.
+    pub fn is_exclusive(&self) -> bool {
+        match self.host_api {
+            HostApiTypeId::ASIO | HostApiTypeId::WDMKS => true,
+            _ => match self.info {
+                #[cfg(windows)]
+                Info::Wasapi(_) => true,
+                _ => false,
+            },
+        }
+    }
+
+    /// Attach the stream info to the given **StreamParameters**, whose device must belong to the
+    /// host API for which the info was constructed.
+    ///
+    /// The parameters are returned unchanged for host APIs that need no stream info.
+    ///
+    /// # Safety
+    ///
+    /// `self` must not be dropped while the returned **StreamParameters** are still used to open a
+    /// **Stream** or to check whether a format is supported.
+    pub unsafe fn attach<S>(&mut self, params: Parameters<S>) -> Parameters<S> {
+        match self.info {
+            Info::None => params,
+            #[cfg(windows)]
+            Info::Wasapi(ref mut info) => {
+                let info: *mut ffi::PaWasapiStreamInfo = &mut **info;
+                params.with_host_api_specific_stream_info(info as *mut raw::c_void)
+            }
+            #[cfg(target_os = "macos")]
+            Info::MacCore(ref mut info) => {
+                let info: *mut ffi::PaMacCoreStreamInfo = &mut **info;
+                params.with_host_api_specific_stream_info(info as *mut raw::c_void)
+            }
+        }
+    }
+}
+
+impl Info {
+    #[cfg(windows)]
+    fn for_host_api(host_api: HostApiTypeId) -> Self {
+        match host_api {
+            HostApiTypeId::WASAPI => Info::Wasapi(Box::new(ffi::PaWasapiStreamInfo {
+                size: ::std::mem::size_of::<ffi::PaWasapiStreamInfo>() as raw::c_ulong,
+                hostApiType: ffi::PaHostApiTypeId_paWASAPI,
+                version: 1,
+                flags: ffi::paWinWasapiExclusive,
+                channelMask: 0,
+                hostProcessorOutput: None,
+                hostProcessorInput: None,
+                threadPriority: ffi::eThreadPriorityNone,
+                streamCategory: ffi::eAudioCategoryOther,
+                streamOption: ffi::eStreamOptionNone,
+            })),
+            _ => Info::None,
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn for_host_api(host_api: HostApiTypeId) -> Self {
+        match host_api {
+            HostApiTypeId::CoreAudio => Info::MacCore(Box::new(ffi::PaMacCoreStreamInfo {
+                size: ::std::mem::size_of::<ffi::PaMacCoreStreamInfo>() as raw::c_ulong,
+                hostApiType: ffi::PaHostApiTypeId_paCoreAudio,
+                version: 0x01,
+                flags: ffi::paMacCoreChangeDeviceParameters
+                    | ffi::paMacCoreFailIfConversionRequired,
+                channelMap: ::std::ptr::null(),
+                channelMapSize: 0,
+            })),
+            _ => Info::None,
+        }
+    }
+
+    #[cfg(not(any(windows, target_os = "macos")))]
+    fn for_host_api(_host_api: HostApiTypeId) -> Self {
+        Info::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive_falls_back_to_shared() {
+        let mut info = ExclusiveStreamInfo::new(HostApiTypeId::ASIO);
+        assert!(info.is_exclusive());
+        let params = ::stream::Parameters::<f32>::new(::DeviceIndex(0), 2, true, 0.01);
+        assert_eq!(unsafe { info.attach(params) }, params);
+
+        let mut info = ExclusiveStreamInfo::new(HostApiTypeId::JACK);
+        assert!(!info.is_exclusive());
+        assert_eq!(info.host_api(), HostApiTypeId::JACK);
+        assert_eq!(unsafe { info.attach(params) }, params);
+    }
+}
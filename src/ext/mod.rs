@@ -21,7 +21,12 @@
 
 //! Portaudio platform specific extensions.
 
+pub mod exclusive;
+
 //#[cfg(any(target_os="macos"))]
 //pub mod mac_core;
 
 // pub mod asio;
+
+#[cfg(all(windows, feature = "win-mme"))]
+pub mod win_mme;
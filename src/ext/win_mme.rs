@@ -0,0 +1,105 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2013 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! The WinMME specific API.
+
+use ffi;
+use std::os::raw;
+use stream::Parameters;
+use types::DeviceIndex;
+
+/// WinMME specific stream info that may be attached to **StreamParameters** in order to use the
+/// low-level latency parameters or to combine multiple devices into a single stream direction.
+///
+/// The info must outlive any use of the **StreamParameters** to which it is attached (see
+/// `WinMmeStreamInfo::attach`).
+pub struct WinMmeStreamInfo {
+    // Boxed so that the pointer given to PortAudio remains valid if `self` is moved.
+    info: Box<ffi::PaWinMmeStreamInfo>,
+    devices: Vec<ffi::PaWinMmeDeviceAndChannelCount>,
+}
+
+impl WinMmeStreamInfo {
+    /// Construct the stream info with no flags set.
+    pub fn new() -> Self {
+        let info = ffi::PaWinMmeStreamInfo {
+            size: ::std::mem::size_of::<ffi::PaWinMmeStreamInfo>() as raw::c_ulong,
+            hostApiType: ffi::PaHostApiTypeId_paMME,
+            version: 1,
+            flags: 0,
+            framesPerBuffer: 0,
+            bufferCount: 0,
+            devices: ::std::ptr::null_mut(),
+            deviceCount: 0,
+            channelMask: 0,
+        };
+        WinMmeStreamInfo {
+            info: Box::new(info),
+            devices: Vec::new(),
+        }
+    }
+
+    /// Use the given low-level buffer size and count rather than those PortAudio derives from the
+    /// suggested latency.
+    pub fn low_level_latency(mut self, frames_per_buffer: u32, buffer_count: u32) -> Self {
+        self.info.flags |= ffi::paWinMmeUseLowLevelLatencyParameters;
+        self.info.framesPerBuffer = frames_per_buffer as raw::c_ulong;
+        self.info.bufferCount = buffer_count as raw::c_ulong;
+        self
+    }
+
+    /// Combine the given devices into a single stream direction.
+    ///
+    /// Each device is given with the number of channels to use on that device. The parameters to
+    /// which this info is attached should be constructed via
+    /// **StreamParameters::host_api_specific_device** with a `channel_count` equal to the total
+    /// number of channels across all devices.
+    pub fn multiple_devices(mut self, devices: &[(DeviceIndex, i32)]) -> Self {
+        self.devices = devices
+            .iter()
+            .map(|&(device, channels)| ffi::PaWinMmeDeviceAndChannelCount {
+                device: device.into(),
+                channelCount: channels as raw::c_int,
+            })
+            .collect();
+        self.info.flags |= ffi::paWinMmeUseMultipleDevices;
+        self.info.devices = self.devices.as_mut_ptr();
+        self.info.deviceCount = self.devices.len() as raw::c_ulong;
+        self
+    }
+
+    /// Attach the stream info to the given **StreamParameters**.
+    ///
+    /// # Safety
+    ///
+    /// `self` must not be dropped while the returned **StreamParameters** are still used to open a
+    /// **Stream** or to check whether a format is supported.
+    pub unsafe fn attach<S>(&mut self, params: Parameters<S>) -> Parameters<S> {
+        let info: *mut ffi::PaWinMmeStreamInfo = &mut *self.info;
+        params.with_host_api_specific_stream_info(info as *mut raw::c_void)
+    }
+}
+
+impl Default for WinMmeStreamInfo {
+    fn default() -> Self {
+        WinMmeStreamInfo::new()
+    }
+}
@@ -0,0 +1,312 @@
+//! Blocking streams whose sample format is negotiated with the device when opened.
+//!
+//! A typed **Stream** fixes its sample format at the type level, so a device that rejects that
+//! format cannot be used at all. The streams within this module are instead opened by trying a
+//! prioritised list of **SampleFormat**s until the device accepts one (see
+//! [**PortAudio::open_with_format_fallback**](../struct.PortAudio.html#method.open_with_format_fallback)).
+//! Audio is read and written in the sample type of the given settings and converted to and from
+//! the stream's actual format on each read or write.
+//!
+//! Only the formats with a corresponding **Sample** type (`F32`, `I32`, `I16`, `I8` and `U8`) can
+//! be tried. Any other formats within the list are skipped.
+
+use std::sync::Arc;
+
+use super::error::Error;
+use super::private::SamplePrivate;
+use super::stream::{
+    Available, Blocking, Buffer, Input, InputSettings, Output, OutputSettings, Stream,
+};
+use super::types::SampleFormat;
+use super::{Life, Sample};
+
+/// Settings that may be used to open a stream via
+/// [**PortAudio::open_with_format_fallback**](../struct.PortAudio.html#method.open_with_format_fallback).
+pub trait FallbackSettings {
+    /// The type of stream that is opened.
+    type Stream;
+    /// Open the stream with the first of the given `formats` that is supported by the device.
+    fn open_with_format_fallback(
+        self,
+        life: Arc<Life>,
+        formats: &[SampleFormat],
+    ) -> Result<(Self::Stream, SampleFormat), Error>;
+}
+
+/// A blocking **Input** stream that reads samples of type **T** regardless of the stream's actual
+/// sample format.
+pub struct FallbackInputStream<T> {
+    stream: AnyInputStream,
+    format: SampleFormat,
+    /// Scratch space holding the converted samples from the most recent read.
+    samples: Vec<T>,
+}
+
+/// A blocking **Output** stream that writes samples of type **T** regardless of the stream's
+/// actual sample format.
+pub struct FallbackOutputStream<T> {
+    stream: AnyOutputStream,
+    format: SampleFormat,
+    /// Scratch space holding the samples written by the user before they are converted.
+    samples: Vec<T>,
+}
+
+/// Declare an enum with a variant for each blocking **Stream** of the given **Flow** that can be
+/// opened at runtime.
+macro_rules! any_stream {
+    ($name:ident, $flow:ident) => {
+        enum $name {
+            F32(Stream<Blocking<Buffer>, $flow<f32>>),
+            I32(Stream<Blocking<Buffer>, $flow<i32>>),
+            I16(Stream<Blocking<Buffer>, $flow<i16>>),
+            I8(Stream<Blocking<Buffer>, $flow<i8>>),
+            U8(Stream<Blocking<Buffer>, $flow<u8>>),
+        }
+    };
+}
+
+any_stream!(AnyInputStream, Input);
+any_stream!(AnyOutputStream, Output);
+
+/// Evaluate `$body` with `$stream` bound to the typed **Stream** within the given `$any` stream.
+macro_rules! with_stream {
+    ($any:expr, $name:ident, $stream:pat => $body:expr) => {
+        match $any {
+            $name::F32($stream) => $body,
+            $name::I32($stream) => $body,
+            $name::I16($stream) => $body,
+            $name::I8($stream) => $body,
+            $name::U8($stream) => $body,
+        }
+    };
+}
+
+/// Try each of the `formats` in order using the given generic `$open` function, returning the
+/// first successfully opened stream.
+///
+/// Only `Error::SampleFormatNotSupported` causes the next format to be tried, any other error is
+/// returned immediately.
+macro_rules! open_with_format_fallback {
+    ($name:ident, $open:ident, $settings:expr, $life:expr, $formats:expr) => {{
+        for &format in $formats {
+            let stream = match format {
+                SampleFormat::F32 => $open::<_, f32>(&$life, &$settings).map($name::F32),
+                SampleFormat::I32 => $open::<_, i32>(&$life, &$settings).map($name::I32),
+                SampleFormat::I16 => $open::<_, i16>(&$life, &$settings).map($name::I16),
+                SampleFormat::I8 => $open::<_, i8>(&$life, &$settings).map($name::I8),
+                SampleFormat::U8 => $open::<_, u8>(&$life, &$settings).map($name::U8),
+                SampleFormat::I24 | SampleFormat::Custom | SampleFormat::Unknown => continue,
+            };
+            match stream {
+                Ok(stream) => return Ok((stream, format)),
+                Err(Error::SampleFormatNotSupported) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(Error::SampleFormatNotSupported)
+    }};
+}
+
+/// Open a blocking **Input** stream with the given settings, but using the sample type **S**.
+fn open_input<T, S>(
+    life: &Arc<Life>,
+    settings: &InputSettings<T>,
+) -> Result<Stream<Blocking<Buffer>, Input<S>>, Error>
+where
+    T: Sample,
+    S: Sample + 'static,
+{
+    let settings = InputSettings::with_flags(
+        settings.params.with_sample_type::<S>(),
+        settings.sample_rate,
+        settings.frames_per_buffer,
+        settings.flags,
+    );
+    Stream::<Blocking<Buffer>, Input<S>>::open(life.clone(), settings)
+}
+
+/// Open a blocking **Output** stream with the given settings, but using the sample type **S**.
+fn open_output<T, S>(
+    life: &Arc<Life>,
+    settings: &OutputSettings<T>,
+) -> Result<Stream<Blocking<Buffer>, Output<S>>, Error>
+where
+    T: Sample,
+    S: Sample + 'static,
+{
+    let settings = OutputSettings::with_flags(
+        settings.params.with_sample_type::<S>(),
+        settings.sample_rate,
+        settings.frames_per_buffer,
+        settings.flags,
+    );
+    Stream::<Blocking<Buffer>, Output<S>>::open(life.clone(), settings)
+}
+
+impl AnyInputStream {
+    fn open<T: Sample>(
+        life: Arc<Life>,
+        settings: InputSettings<T>,
+        formats: &[SampleFormat],
+    ) -> Result<(Self, SampleFormat), Error> {
+        open_with_format_fallback!(AnyInputStream, open_input, settings, life, formats)
+    }
+}
+
+impl AnyOutputStream {
+    fn open<T: Sample>(
+        life: Arc<Life>,
+        settings: OutputSettings<T>,
+        formats: &[SampleFormat],
+    ) -> Result<(Self, SampleFormat), Error> {
+        open_with_format_fallback!(AnyOutputStream, open_output, settings, life, formats)
+    }
+}
+
+impl<T: Sample + 'static> FallbackSettings for InputSettings<T> {
+    type Stream = FallbackInputStream<T>;
+    fn open_with_format_fallback(
+        self,
+        life: Arc<Life>,
+        formats: &[SampleFormat],
+    ) -> Result<(Self::Stream, SampleFormat), Error> {
+        let (stream, format) = AnyInputStream::open(life, self, formats)?;
+        let stream = FallbackInputStream {
+            stream: stream,
+            format: format,
+            samples: Vec::new(),
+        };
+        Ok((stream, format))
+    }
+}
+
+impl<T: Sample + 'static> FallbackSettings for OutputSettings<T> {
+    type Stream = FallbackOutputStream<T>;
+    fn open_with_format_fallback(
+        self,
+        life: Arc<Life>,
+        formats: &[SampleFormat],
+    ) -> Result<(Self::Stream, SampleFormat), Error> {
+        let (stream, format) = AnyOutputStream::open(life, self, formats)?;
+        let stream = FallbackOutputStream {
+            stream: stream,
+            format: format,
+            samples: Vec::new(),
+        };
+        Ok((stream, format))
+    }
+}
+
+/// Convert the given `samples` into the `scratch` buffer.
+///
+/// The `scratch` buffer is reused between calls and only reallocates when it must grow beyond its
+/// existing capacity, so converting a steady number of samples does not allocate.
+fn convert_into<S: Sample, T: Sample>(samples: &[S], scratch: &mut Vec<T>) {
+    scratch.clear();
+    scratch.extend(
+        samples
+            .iter()
+            .map(|&sample| T::from_normalised_f32(sample.to_normalised_f32())),
+    );
+}
+
+/// Resize the `scratch` buffer to `len` silent samples, reusing its existing allocation where
+/// possible.
+fn silent_scratch<T: Sample>(scratch: &mut Vec<T>, len: usize) -> &mut [T] {
+    scratch.clear();
+    scratch.resize(len, T::SILENCE);
+    &mut scratch[..]
+}
+
+/// Methods shared by both the **FallbackInputStream** and **FallbackOutputStream**.
+macro_rules! impl_fallback_stream {
+    ($name:ident, $any:ident) => {
+        impl<T: Sample> $name<T> {
+            /// The sample format that the stream was opened with.
+            pub fn sample_format(&self) -> SampleFormat {
+                self.format
+            }
+
+            /// Commences audio processing. See **Stream::start**.
+            pub fn start(&mut self) -> Result<(), Error> {
+                with_stream!(self.stream, $any, ref mut stream => stream.start())
+            }
+
+            /// Terminates audio processing once pending buffers have completed. See
+            /// **Stream::stop**.
+            pub fn stop(&mut self) -> Result<(), Error> {
+                with_stream!(self.stream, $any, ref mut stream => stream.stop())
+            }
+
+            /// Terminates audio processing immediately. See **Stream::abort**.
+            pub fn abort(&mut self) -> Result<(), Error> {
+                with_stream!(self.stream, $any, ref mut stream => stream.abort())
+            }
+
+            /// Closes the stream. See **Stream::close**.
+            pub fn close(&mut self) -> Result<(), Error> {
+                with_stream!(self.stream, $any, ref mut stream => stream.close())
+            }
+
+            /// Determine whether the stream is stopped. See **Stream::is_stopped**.
+            pub fn is_stopped(&self) -> Result<bool, Error> {
+                with_stream!(self.stream, $any, ref stream => stream.is_stopped())
+            }
+
+            /// Determine whether the stream is active. See **Stream::is_active**.
+            pub fn is_active(&self) -> Result<bool, Error> {
+                with_stream!(self.stream, $any, ref stream => stream.is_active())
+            }
+        }
+    };
+}
+
+impl_fallback_stream!(FallbackInputStream, AnyInputStream);
+impl_fallback_stream!(FallbackOutputStream, AnyOutputStream);
+
+impl<T: Sample> FallbackInputStream<T> {
+    /// Retrieve the number of frames that can be read from the stream without waiting. See
+    /// **Stream::read_available**.
+    pub fn read_available(&self) -> Result<Available, Error> {
+        with_stream!(self.stream, AnyInputStream, ref stream => stream.read_available())
+    }
+
+    /// Read the given number of frames from the stream, converted to **T**.
+    ///
+    /// Returns an interleaved slice containing the read audio data. See **Stream::read**.
+    pub fn read(&mut self, frames: u32) -> Result<&[T], Error> {
+        let samples = &mut self.samples;
+        with_stream!(self.stream, AnyInputStream, ref stream => {
+            convert_into(stream.read(frames)?, samples);
+        });
+        Ok(&self.samples[..])
+    }
+}
+
+impl<T: Sample> FallbackOutputStream<T> {
+    /// Retrieve the number of frames that can be written to the stream without waiting. See
+    /// **Stream::write_available**.
+    pub fn write_available(&self) -> Result<Available, Error> {
+        with_stream!(self.stream, AnyOutputStream, ref stream => stream.write_available())
+    }
+
+    /// Write the given number of frames to the stream.
+    ///
+    /// The `write_fn` is given an interleaved buffer of **T** to fill, which is then converted to
+    /// the stream's sample format. See **Stream::write**.
+    pub fn write<WF>(&mut self, frames: u32, write_fn: WF) -> Result<(), Error>
+    where
+        WF: for<'b> FnOnce(&'b mut [T]),
+    {
+        let samples = &mut self.samples;
+        with_stream!(self.stream, AnyOutputStream, ref mut stream => {
+            let len = frames as usize * stream.samples_per_frame();
+            write_fn(silent_scratch(samples, len));
+            stream.write(frames, |buffer| {
+                for (out, &sample) in buffer.iter_mut().zip(samples.iter()) {
+                    *out = SamplePrivate::from_normalised_f32(sample.to_normalised_f32());
+                }
+            })
+        })
+    }
+}
@@ -56,39 +56,65 @@ extern crate bitflags;
 extern crate libc;
 extern crate num;
 extern crate portaudio_sys as ffi;
+#[cfg(feature = "serde")]
+extern crate serde;
 
 use num::FromPrimitive;
 use std::option::Option;
 use std::os::raw;
 
+pub use capture::{CaptureReader, OverflowPolicy};
+#[cfg(target_os = "linux")]
+pub use error::AlsaConfigError;
 pub use error::Error;
+pub use ext::exclusive::ExclusiveStreamInfo;
+pub use fallback::{FallbackInputStream, FallbackOutputStream, FallbackSettings};
 pub use ffi::{
     PaStreamCallbackResult as StreamCallbackResult, PA_ABORT as Abort, PA_COMPLETE as Complete,
     PA_CONTINUE as Continue,
 };
+pub use loopback::Tolerance as LoopbackTolerance;
+#[cfg(feature = "varispeed")]
+pub use playback::ResampleQuality;
+pub use playback::{PlaybackStream, StreamWriter};
+pub use report::{AudioReport, BuildInfo, DeviceReport, HostApiReport};
 pub use stream::{
     callback_flags as stream_callback_flags, flags as stream_flags, Available as StreamAvailable,
-    Blocking, CallbackFlags as StreamCallbackFlags, CallbackTimeInfo as StreamCallbackTimeInfo,
-    Duplex, DuplexCallbackArgs as DuplexStreamCallbackArgs, DuplexSettings as DuplexStreamSettings,
+    Blocking, BufferStats as StreamBufferStats, CallbackFlags as StreamCallbackFlags,
+    CallbackTimeInfo as StreamCallbackTimeInfo, Duplex,
+    DuplexCallbackArgs as DuplexStreamCallbackArgs, DuplexSettings as DuplexStreamSettings,
     Flags as StreamFlags, Flow, Info as StreamInfo, Input,
     InputCallbackArgs as InputStreamCallbackArgs, InputSettings as InputStreamSettings,
-    NonBlocking, Output, OutputCallbackArgs as OutputStreamCallbackArgs,
-    OutputSettings as OutputStreamSettings, Parameters as StreamParameters,
-    Settings as StreamSettings, Stream,
+    Level as StreamLevel, NonBlocking, Output, OutputCallbackArgs as OutputStreamCallbackArgs,
+    OutputSettings as OutputStreamSettings, PanicPayload as StreamPanicPayload,
+    Parameters as StreamParameters, Raw, Settings as StreamSettings, Snapshot as StreamSnapshot,
+    Stream, StreamController, StreamReader, Xruns as StreamXruns,
 };
+#[cfg(target_os = "linux")]
+pub use types::AlsaConfig;
 pub use types::{
-    DeviceIndex, DeviceInfo, Frames, HostApiIndex, HostApiInfo, HostApiTypeId, HostErrorInfo,
-    SampleFormat, Time, FRAMES_PER_BUFFER_UNSPECIFIED,
+    DeviceIndex, DeviceInfo, Direction, Frames, HostApiIndex, HostApiInfo, HostApiTypeId,
+    HostErrorInfo, LatencyClass, Role, SampleFormat, StableDeviceId, Time,
+    FRAMES_PER_BUFFER_UNSPECIFIED,
 };
 
 use std::ptr;
 
 #[macro_use]
 mod enum_primitive;
+mod atomic;
+pub mod capture;
 pub mod error;
 pub mod ext;
+pub mod fallback;
+pub mod loopback;
+pub mod playback;
+pub mod report;
 pub mod stream;
 mod types;
+pub mod util;
+#[cfg(windows)]
+mod wasapi;
 
 /// A type-safe wrapper around the PortAudio API.
 ///
@@ -100,6 +126,8 @@ pub struct PortAudio {
     ///
     /// The lifetime is shared between `PortAudio` and all its spawned `Stream`s.
     life: std::sync::Arc<Life>,
+    /// The host API whose defaults are used in place of PortAudio's global defaults, if any.
+    preferred_host_api: Option<HostApiTypeId>,
 }
 
 /// The lifetime of the `PortAudio` instance.
@@ -110,6 +138,10 @@ pub struct Life {
     /// This is solely used for checking whether or not the PortAudio API has already been
     /// terminated manually (via the `PortAudio::terminate` method) when `Drop::drop` is called.
     is_terminated: std::sync::Mutex<bool>,
+    /// The number of `Stream`s currently holding one of the `max_open_streams` slots.
+    open_streams: std::sync::atomic::AtomicUsize,
+    /// The limit set via `PortAudio::set_max_concurrent_streams`, or `usize::MAX` if unlimited.
+    max_open_streams: std::sync::atomic::AtomicUsize,
 }
 
 impl PortAudio {
@@ -125,16 +157,99 @@ impl PortAudio {
     /// It is safe to simultaneously construct more than one **PortAudio** instance, however this
     /// is rarely necessary.
     pub fn new() -> Result<Self, Error> {
-        unsafe {
-            let error = FromPrimitive::from_i32(ffi::Pa_Initialize()).unwrap();
-            match error {
-                Error::NoError => {
-                    let life = std::sync::Arc::new(Life {
-                        is_terminated: std::sync::Mutex::new(false),
-                    });
-                    Ok(PortAudio { life: life })
-                }
-                err => Err(err),
+        initialize()?;
+        let life = std::sync::Arc::new(Life {
+            is_terminated: std::sync::Mutex::new(false),
+            open_streams: std::sync::atomic::AtomicUsize::new(0),
+            max_open_streams: std::sync::atomic::AtomicUsize::new(usize::MAX),
+        });
+        Ok(PortAudio {
+            life: life,
+            preferred_host_api: None,
+        })
+    }
+
+    /// Construct a **PortAudio** instance that prefers the given host API over PortAudio's default
+    /// host API.
+    ///
+    /// PortAudio fixes its default host API during initialization (e.g. ALSA rather than JACK on
+    /// Linux). The returned instance instead resolves `default_host_api`, `default_input_device`,
+    /// `default_output_device` and the `default_*_stream_params` methods using the given host API
+    /// and its default devices.
+    ///
+    /// Returns `Err(Error::HostApiNotFound)` if the host API is not available.
+    pub fn new_preferring(host_api: HostApiTypeId) -> Result<Self, Error> {
+        let mut pa = PortAudio::new()?;
+        pa.host_api_type_id_to_host_api_index(host_api)?;
+        pa.preferred_host_api = Some(host_api);
+        Ok(pa)
+    }
+
+    /// Construct a **PortAudio** instance whose ALSA host API uses the given configuration.
+    ///
+    /// ALSA reads its configuration from the files listed in the `ALSA_CONFIG_PATH` environment
+    /// variable. Containers often lack these files, in which case initialization prints a flood of
+    /// errors and finds no devices. This sets `ALSA_CONFIG_PATH` to the given configuration before
+    /// calling `Pa_Initialize`, e.g. so that a container with a forwarded PulseAudio or PipeWire
+    /// socket can use it via a minimal configuration.
+    ///
+    /// An **AlsaConfig::Inline** configuration is written to a newly created file that only the
+    /// current user may access, named `portaudio-rs-alsa-<pid>-<unique suffix>.conf` in the
+    /// system's temporary directory. An existing file is never opened or overwritten.
+    ///
+    /// The configuration is read while PortAudio enumerates the ALSA devices. Once this returns,
+    /// whether successfully or not, `ALSA_CONFIG_PATH` is restored to its previous value (or
+    /// removed if it was unset) and the file written for an inline configuration is deleted.
+    ///
+    /// **This must be called before PortAudio is otherwise used and before any other threads are
+    /// spawned**, as modifying the environment while another thread reads it (e.g. ALSA or
+    /// `getenv` within another library) is undefined behaviour on most platforms.
+    ///
+    /// Returns `Err(AlsaConfigError::Io)` if the configuration file does not exist or cannot be
+    /// written, or `Err(AlsaConfigError::PortAudio(Error::HostApiNotFound))` if the ALSA host API
+    /// is not available.
+    #[cfg(target_os = "linux")]
+    pub fn new_with_alsa_config(config: AlsaConfig) -> Result<Self, AlsaConfigError> {
+        let (path, is_temporary) = match config {
+            AlsaConfig::Path(path) => {
+                std::fs::metadata(path).map_err(AlsaConfigError::Io)?;
+                (path.to_path_buf(), false)
+            }
+            AlsaConfig::Inline(contents) => {
+                let path = write_private_alsa_config(contents).map_err(AlsaConfigError::Io)?;
+                (path, true)
+            }
+        };
+        let previous = std::env::var_os("ALSA_CONFIG_PATH");
+        std::env::set_var("ALSA_CONFIG_PATH", &path);
+        let result = PortAudio::new().and_then(|pa| {
+            pa.host_api_type_id_to_host_api_index(HostApiTypeId::ALSA)?;
+            Ok(pa)
+        });
+        match previous {
+            Some(previous) => std::env::set_var("ALSA_CONFIG_PATH", previous),
+            None => std::env::remove_var("ALSA_CONFIG_PATH"),
+        }
+        if is_temporary {
+            let _ = std::fs::remove_file(&path);
+        }
+        Ok(result?)
+    }
+
+    /// The host API preferred via **PortAudio::new_preferring**, if any.
+    pub fn preferred_host_api(&self) -> Option<HostApiTypeId> {
+        self.preferred_host_api
+    }
+
+    /// The info for the host API preferred via **PortAudio::new_preferring**, if any.
+    fn preferred_host_api_info(&self) -> Result<Option<HostApiInfo<'_>>, Error> {
+        match self.preferred_host_api {
+            None => Ok(None),
+            Some(type_id) => {
+                let idx = self.host_api_type_id_to_host_api_index(type_id)?;
+                self.host_api_info(idx)
+                    .map(Some)
+                    .ok_or(Error::HostApiNotFound)
             }
         }
     }
@@ -151,6 +266,85 @@ impl PortAudio {
         terminate()
     }
 
+    /// Force PortAudio to re-enumerate the available devices.
+    ///
+    /// PortAudio builds its list of devices once during initialization, so devices that are
+    /// plugged in or removed afterwards are not reflected by `devices`, `device_count` or
+    /// `device_info` (stale indices may even refer to the wrong device). This method terminates
+    /// and re-initializes the PortAudio API using a matching `Pa_Terminate`/`Pa_Initialize` pair so
+    /// that subsequent enumeration reflects the current hardware.
+    ///
+    /// **All Streams opened via this PortAudio instance must be dropped before calling this
+    /// method**, as termination would close any streams that are still open and invalidate them.
+    /// Returns `Err(Error::StreamIsNotStopped)` if any such **Stream** is still alive.
+    ///
+    /// PortAudio reference counts initialization, so the device list is only refreshed if this is
+    /// the only **PortAudio** instance alive within the process. Other instances are unaffected by
+    /// this call.
+    ///
+    /// If re-initialization fails, PortAudio is left terminated and the error is returned. The
+    /// instance will not attempt to terminate PortAudio a second time when dropped.
+    pub fn refresh_devices(&mut self) -> Result<(), Error> {
+        // Every **Stream** holds a reference to the instance's `Life`.
+        if std::sync::Arc::strong_count(&self.life) > 1 {
+            return Err(Error::StreamIsNotStopped);
+        }
+        terminate()?;
+        match initialize() {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                *self.life.is_terminated.lock().unwrap() = true;
+                Err(err)
+            }
+        }
+    }
+
+    /// The **DeviceInfo::stable_id** of the given device info, looking up its host API type.
+    fn stable_id_of(&self, info: &DeviceInfo) -> StableDeviceId {
+        let host_type = self.host_api_info(info.host_api);
+        info.stable_id_with_host_type(host_type.map_or(-1, |host| host.host_type as i64))
+    }
+
+    /// Limit the number of streams that may be open at once via this **PortAudio** instance, or
+    /// remove the limit with `None` (the default).
+    ///
+    /// Opening a stream while `max` streams are already open fails with
+    /// `Err(Error::DeviceUnavailable)`. A stream stops counting towards the limit once it is
+    /// closed or dropped. Lowering the limit does not affect streams that are already open.
+    ///
+    /// PortAudio manages its own threads, so this limit is the only means of bounding them. Most
+    /// host APIs (e.g. ALSA, WASAPI, WinMME and DirectSound) run a dedicated thread for each
+    /// non-blocking stream, Core Audio runs callbacks on an I/O thread per device and JACK
+    /// processes all streams on its single process thread. PortAudio cannot coalesce streams, so
+    /// applications playing many sounds at once (e.g. soundboards) should prefer mixing them
+    /// within the callback of a single stream per device.
+    pub fn set_max_concurrent_streams(&self, max: Option<usize>) {
+        let max = max.unwrap_or(usize::MAX);
+        self.life
+            .max_open_streams
+            .store(max, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// The limit set via **PortAudio::set_max_concurrent_streams**, if any.
+    pub fn max_concurrent_streams(&self) -> Option<usize> {
+        match self
+            .life
+            .max_open_streams
+            .load(std::sync::atomic::Ordering::SeqCst)
+        {
+            usize::MAX => None,
+            max => Some(max),
+        }
+    }
+
+    /// The number of streams opened via this **PortAudio** instance that have not yet been closed
+    /// or dropped.
+    pub fn open_stream_count(&self) -> usize {
+        self.life
+            .open_streams
+            .load(std::sync::atomic::Ordering::SeqCst)
+    }
+
     /// Retrieve the release number of the currently running PortAudio build.
     pub fn version(&self) -> i32 {
         version()
@@ -163,6 +357,25 @@ impl PortAudio {
         version_text()
     }
 
+    /// Extract details of the PortAudio build, such as its revision, from its version information.
+    ///
+    /// The fields are parsed on a best-effort basis from the version text (see
+    /// **BuildInfo::parse**), falling back to the release number for the version. Fields that
+    /// cannot be determined are `None`.
+    ///
+    /// `Pa_GetVersionInfo` is deliberately not used, as it is missing from PortAudio releases
+    /// before 19.5.0, which may still be found on the system via pkg-config.
+    pub fn build_info(&self) -> BuildInfo {
+        let mut info = BuildInfo::parse(self.version_text().unwrap_or(""));
+        let version = self.version();
+        if info.version.is_none() && version >= 1 << 16 {
+            // Since 19.5.0 the version number is encoded as `major << 16 | minor << 8 | sub_minor`.
+            // Earlier releases return an unencoded number such as `1899`, which is ignored.
+            info.version = Some((version >> 16, (version >> 8) & 0xff, version & 0xff));
+        }
+        info
+    }
+
     /// Produces an iterator yielding the **DeviceIndex** for each device along with their
     /// respective **DeviceInfo**s.
     pub fn devices(&self) -> Result<Devices, Error> {
@@ -198,10 +411,16 @@ impl PortAudio {
     ///
     /// Returns the default output device index for the default host API.
     ///
+    /// If this instance was constructed via **PortAudio::new_preferring**, returns the default
+    /// input device of the preferred host API instead.
+    ///
     /// Returns `Error` if no default input device is available or an error was encountered.
     ///
     /// **TODO:** Investigate exactly what errors may occur as the PA docs aren't clear on this.
     pub fn default_input_device(&self) -> Result<DeviceIndex, Error> {
+        if let Some(info) = self.preferred_host_api_info()? {
+            return info.default_input_device.ok_or(Error::NoDevice);
+        }
         match unsafe { ffi::Pa_GetDefaultInputDevice() } {
             idx if idx >= 0 => Ok(DeviceIndex(idx as u32)),
             err => Err(::num::FromPrimitive::from_i32(err).unwrap()),
@@ -213,21 +432,89 @@ impl PortAudio {
     ///
     /// Returns the default input device index for the default host API.
     ///
+    /// If this instance was constructed via **PortAudio::new_preferring**, returns the default
+    /// output device of the preferred host API instead.
+    ///
     /// Returns `Error` if no default input device is available or an error was encountered.
     ///
     /// **TODO:** Investigate exactly what errors may occur as the PA docs aren't clear on this.
     pub fn default_output_device(&self) -> Result<DeviceIndex, Error> {
+        if let Some(info) = self.preferred_host_api_info()? {
+            return info.default_output_device.ok_or(Error::NoDevice);
+        }
         match unsafe { ffi::Pa_GetDefaultOutputDevice() } {
             idx if idx >= 0 => Ok(DeviceIndex(idx as u32)),
             err => Err(::num::FromPrimitive::from_i32(err).unwrap()),
         }
     }
 
+    /// Retrieve the index of the default device for the given `role` in the given `direction`.
+    ///
+    /// On Windows, this is the WASAPI device of the default endpoint for the `role`, e.g. the
+    /// headset chosen as the default communications device rather than the speakers. If that
+    /// endpoint cannot be found among PortAudio's WASAPI devices, or on other platforms where
+    /// there is a single default device for every role, this is the same as
+    /// **PortAudio::default_input_device** or **PortAudio::default_output_device**.
+    pub fn default_device_for_role(
+        &self,
+        role: Role,
+        direction: Direction,
+    ) -> Result<DeviceIndex, Error> {
+        if let Some(idx) = self.device_for_role(role, direction)? {
+            return Ok(idx);
+        }
+        match direction {
+            Direction::Input => self.default_input_device(),
+            Direction::Output => self.default_output_device(),
+        }
+    }
+
+    /// The WASAPI device of the default endpoint for the given `role` and `direction`, if any.
+    #[cfg(windows)]
+    fn device_for_role(
+        &self,
+        role: Role,
+        direction: Direction,
+    ) -> Result<Option<DeviceIndex>, Error> {
+        let name = match wasapi::default_endpoint_name(direction, role) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let host_api = match self.host_api_type_id_to_host_api_index(HostApiTypeId::WASAPI) {
+            Ok(host_api) => host_api,
+            Err(Error::HostApiNotFound) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        for device in self.host_api_devices(host_api)? {
+            let (idx, info) = device?;
+            let channels = match direction {
+                Direction::Input => info.max_input_channels,
+                Direction::Output => info.max_output_channels,
+            };
+            if channels > 0 && info.name == name {
+                return Ok(Some(idx));
+            }
+        }
+        Ok(None)
+    }
+
+    /// There is a single default device for every role on platforms other than Windows.
+    #[cfg(not(windows))]
+    fn device_for_role(
+        &self,
+        _role: Role,
+        _direction: Direction,
+    ) -> Result<Option<DeviceIndex>, Error> {
+        Ok(None)
+    }
+
     /// Retrieve a **DeviceInfo** structure containing information about the specified device.
     ///
     /// Returns `Ok(DeviceInfo)` if successful.
     ///
-    /// Returns `Err(Error::InvalidDevice)` if the device parameter is out of range.
+    /// Returns `Err(Error::InvalidDevice)` if the device parameter is out of range. This includes
+    /// the index PortAudio uses to represent "no device" as well as stale indices that no longer
+    /// refer to a device (e.g. after calling `refresh_devices`).
     ///
     /// # Arguments
     ///
@@ -241,6 +528,133 @@ impl PortAudio {
         }
     }
 
+    /// Construct the stream info requesting exclusive access to the given `device`, via whichever
+    /// mechanism its host API provides.
+    ///
+    /// Attach the info to the **StreamParameters** for the `device` via
+    /// [**ExclusiveStreamInfo::attach**](./ext/exclusive/struct.ExclusiveStreamInfo.html#method.attach).
+    /// Where the host API does not support exclusive access the stream is opened in shared mode,
+    /// which is indicated by **ExclusiveStreamInfo::is_exclusive**. See
+    /// [**ExclusiveStreamInfo**](./ext/exclusive/struct.ExclusiveStreamInfo.html) for the behaviour
+    /// of each host API.
+    ///
+    /// Returns `Err(Error::InvalidDevice)` if there is no such device.
+    pub fn exclusive_stream_info(&self, device: DeviceIndex) -> Result<ExclusiveStreamInfo, Error> {
+        let host_api = self.device_info(device)?.host_api;
+        let host_api = self.host_api_info(host_api).ok_or(Error::InvalidHostApi)?;
+        Ok(ExclusiveStreamInfo::new(host_api.host_type))
+    }
+
+    /// Collect everything PortAudio knows about the audio setup of the machine into a single
+    /// **AudioReport**.
+    ///
+    /// This includes the PortAudio version, all host APIs, all devices along with the standard
+    /// sample rates that they support, and the default devices. With the `serde` feature enabled
+    /// the report implements `Serialize`, so it may be converted to e.g. JSON for attaching to a
+    /// bug report.
+    ///
+    /// Checking the supported sample rates of every device may take a moment on some host APIs.
+    pub fn probe(&self) -> Result<AudioReport, Error> {
+        const INTERLEAVED: bool = true;
+        let supported_rates = |params: Option<StreamParameters<f32>>, input: bool| -> Vec<f64> {
+            let params = match params {
+                Some(params) => params,
+                None => return Vec::new(),
+            };
+            report::STANDARD_SAMPLE_RATES
+                .iter()
+                .cloned()
+                .filter(|&rate| {
+                    if input {
+                        self.is_input_format_supported(params, rate).is_ok()
+                    } else {
+                        self.is_output_format_supported(params, rate).is_ok()
+                    }
+                })
+                .collect()
+        };
+
+        let host_apis = self
+            .host_apis()
+            .map(|(index, info)| HostApiReport {
+                index: index,
+                host_type: info.host_type,
+                name: info.name.to_string(),
+                device_count: info.device_count,
+                default_input_device: info.default_input_device,
+                default_output_device: info.default_output_device,
+            })
+            .collect();
+
+        let mut devices = Vec::new();
+        for device in self.devices()? {
+            let (idx, info) = device?;
+            let in_params = match info.max_input_channels {
+                0 => None,
+                channels => Some(StreamParameters::new(idx, channels, INTERLEAVED, 0.0)),
+            };
+            let out_params = match info.max_output_channels {
+                0 => None,
+                channels => Some(StreamParameters::new(idx, channels, INTERLEAVED, 0.0)),
+            };
+            devices.push(DeviceReport {
+                index: idx,
+                stable_id: self.stable_id_of(&info),
+                name: info.name.to_string(),
+                host_api: info.host_api,
+                max_input_channels: info.max_input_channels,
+                max_output_channels: info.max_output_channels,
+                default_low_input_latency: info.default_low_input_latency,
+                default_low_output_latency: info.default_low_output_latency,
+                default_high_input_latency: info.default_high_input_latency,
+                default_high_output_latency: info.default_high_output_latency,
+                default_sample_rate: info.default_sample_rate,
+                input_sample_rates: supported_rates(in_params, true),
+                output_sample_rates: supported_rates(out_params, false),
+            });
+        }
+
+        Ok(AudioReport {
+            version: self.version(),
+            version_text: self
+                .version_text()
+                .unwrap_or("<Failed to convert str from CStr>")
+                .to_string(),
+            default_host_api: self.default_host_api().ok(),
+            default_input_device: self.default_input_device().ok(),
+            default_output_device: self.default_output_device().ok(),
+            host_apis: host_apis,
+            devices: devices,
+        })
+    }
+
+    /// The **StableDeviceId** of the given device. See **DeviceInfo::stable_id**.
+    ///
+    /// Returns `Err(Error::InvalidDevice)` if the device is not available.
+    pub fn device_stable_id(&self, device: DeviceIndex) -> Result<StableDeviceId, Error> {
+        let info = self.device_info(device)?;
+        Ok(self.stable_id_of(&info))
+    }
+
+    /// Find the device with the given **StableDeviceId** (see **DeviceInfo::stable_id**).
+    ///
+    /// Returns `Ok(None)` if no currently available device matches the identifier.
+    ///
+    /// As identical devices share the same identifier, the device with the lowest **DeviceIndex**
+    /// is returned if more than one device matches.
+    pub fn find_device_by_stable_id(
+        &self,
+        id: StableDeviceId,
+    ) -> Result<Option<(DeviceIndex, DeviceInfo<'_>)>, Error> {
+        for device in self.devices()? {
+            let (idx, info) = device?;
+            if self.stable_id_of(&info) == id {
+                return Ok(Some((idx, info)));
+            }
+        }
+        Ok(None)
+    }
+
     /// Produces an iterator yielding the **HostApiIndex** of each available API along with their
     /// respective **HostApiInfo**s.
     pub fn host_apis(&self) -> HostApis {
@@ -251,6 +665,45 @@ impl PortAudio {
         }
     }
 
+    /// Produces an iterator yielding the **DeviceIndex** for each device of the given host API
+    /// along with their respective **DeviceInfo**s.
+    ///
+    /// The host API's devices are translated to global **DeviceIndex**es via
+    /// **PortAudio::api_device_index_to_device_index**, so they may be used anywhere else.
+    ///
+    /// Returns `Err(Error::InvalidHostApi)` if the `host_api` is out of range.
+    pub fn host_api_devices(&self, host_api: HostApiIndex) -> Result<HostApiDevices<'_>, Error> {
+        let info = self.host_api_info(host_api).ok_or(Error::InvalidHostApi)?;
+        Ok(HostApiDevices {
+            host_api: host_api,
+            total: info.device_count,
+            next: 0,
+            port_audio: self,
+        })
+    }
+
+    /// Produces the available host APIs ordered from most to least preferable.
+    ///
+    /// The order is determined by the following, in order of priority:
+    ///
+    /// 1. Host APIs without any devices are placed last, as they cannot be used.
+    /// 2. The host API's **LatencyClass**, lowest first (see **HostApiTypeId::latency_class**).
+    /// 3. The number of devices provided by the host API, most first.
+    ///
+    /// Host APIs that are otherwise equal retain the order in which PortAudio lists them. The best
+    /// available host API is therefore the first, though users may of course select another.
+    pub fn host_apis_by_preference(&self) -> Vec<(HostApiIndex, HostApiInfo<'_>)> {
+        let mut host_apis: Vec<_> = self.host_apis().collect();
+        host_apis.sort_by_key(|(_, info)| {
+            (
+                info.device_count == 0,
+                info.host_type.latency_class(),
+                std::cmp::Reverse(info.device_count),
+            )
+        });
+        host_apis
+    }
+
     /// Retrieve the number of available host APIs.
     ///
     /// Even if a host API is available it may have no devices available.
@@ -271,8 +724,14 @@ impl PortAudio {
     /// Return a non-negative value ranging from 0 to (get_host_api_count()-1) indicating the
     /// default host API index or an `Error` if an error is encountered.
     ///
+    /// If this instance was constructed via **PortAudio::new_preferring**, returns the index of
+    /// the preferred host API instead.
+    ///
     /// TODO: Determine exactly what errors might occur (PA docs aren't clear on this).
     pub fn default_host_api(&self) -> Result<HostApiIndex, Error> {
+        if let Some(type_id) = self.preferred_host_api {
+            return self.host_api_type_id_to_host_api_index(type_id);
+        }
         unsafe { result_from_host_api_index(ffi::Pa_GetDefaultHostApi()) }
     }
 
@@ -396,6 +855,58 @@ impl PortAudio {
         is_format_supported(Some(in_params.into()), Some(out_params.into()), sample_rate)
     }
 
+    /// Determine the recommended sample format for streams in the given `direction` on the given
+    /// `device`.
+    ///
+    /// Opening a stream in the device's native format avoids a conversion within PortAudio. The
+    /// formats preferred by the device's host API (see **HostApiTypeId::preferred_sample_formats**)
+    /// are probed in order at the device's default sample rate and the first supported format is
+    /// returned. Note that many host APIs report support for formats that they convert internally,
+    /// so the host API's preference takes priority over probing.
+    ///
+    /// Returns `Err(Error::InvalidChannelCount)` if the device has no channels in the given
+    /// `direction`, or `Err(Error::SampleFormatNotSupported)` if none of the preferred formats are
+    /// supported.
+    pub fn default_sample_format(
+        &self,
+        device: DeviceIndex,
+        direction: Direction,
+    ) -> Result<SampleFormat, Error> {
+        let info = self.device_info(device)?;
+        let (max_channels, latency) = match direction {
+            Direction::Input => (info.max_input_channels, info.default_low_input_latency),
+            Direction::Output => (info.max_output_channels, info.default_low_output_latency),
+        };
+        if max_channels <= 0 {
+            return Err(Error::InvalidChannelCount);
+        }
+        let formats = match self.host_api_info(info.host_api) {
+            Some(host_api) => host_api.host_type.preferred_sample_formats(),
+            None => HostApiTypeId::InDevelopment.preferred_sample_formats(),
+        };
+        for &format in formats {
+            let params = ffi::PaStreamParameters {
+                device: device.into(),
+                channelCount: ::std::cmp::min(max_channels, 2),
+                sampleFormat: format.flags().bits(),
+                suggestedLatency: latency,
+                hostApiSpecificStreamInfo: ptr::null_mut(),
+            };
+            let supported = match direction {
+                Direction::Input => {
+                    is_format_supported(Some(params), None, info.default_sample_rate)
+                }
+                Direction::Output => {
+                    is_format_supported(None, Some(params), info.default_sample_rate)
+                }
+            };
+            if supported.is_ok() {
+                return Ok(format);
+            }
+        }
+        Err(Error::SampleFormatNotSupported)
+    }
+
     /// Open a new blocking [**Stream**](./stream/struct.Stream.html) with the given settings.
     ///
     /// The **Stream** will be opened in **Blocking** "read/write" mode.
@@ -418,6 +929,65 @@ impl PortAudio {
         Stream::<Blocking<<S::Flow as Flow>::Buffer>, S::Flow>::open(self.life.clone(), settings)
     }
 
+    /// Open a new blocking stream with the given settings, trying each of the given sample
+    /// `formats` in order until the device accepts one.
+    ///
+    /// The sample type of the `settings` is the type in which audio is read or written, while the
+    /// stream itself uses the first format that the device supports. Samples are converted between
+    /// the two on each read or write.
+    ///
+    /// Returns the opened stream along with the **SampleFormat** that succeeded.
+    ///
+    /// See [**fallback**](./fallback/index.html) for details.
+    pub fn open_with_format_fallback<S>(
+        &self,
+        settings: S,
+        formats: &[SampleFormat],
+    ) -> Result<(S::Stream, SampleFormat), Error>
+    where
+        S: FallbackSettings,
+    {
+        settings.open_with_format_fallback(self.life.clone(), formats)
+    }
+
+    /// Open a new blocking [**Stream**](./stream/struct.Stream.html) from raw
+    /// `PaStreamParameters`, bypassing the typed **Parameters** and **Settings**.
+    ///
+    /// This is an escape hatch for configurations that the typed API does not model, or for
+    /// parameters constructed by another library. At least one of `in_params` and `out_params`
+    /// should be given. Audio is transferred via the unsafe **Stream::read_raw** and
+    /// **Stream::write_raw** methods, while the rest of the **Stream** API (e.g. **Stream::start**,
+    /// **Stream::info** and **Stream::sample_format**) is available as usual.
+    ///
+    /// The returned **Stream** is inactive (stopped).
+    ///
+    /// # Safety
+    ///
+    /// The parameters are passed to `Pa_OpenStream` as they are, so the caller must ensure that:
+    ///
+    /// - Each `device` is a valid device index for this PortAudio instance (or
+    ///   `paUseHostApiSpecificDeviceSpecification` where the host API supports it).
+    /// - Each `sampleFormat` is a valid combination of `PaSampleFormat` flags.
+    /// - Each `hostApiSpecificStreamInfo` is either null or points to a struct of the layout that
+    ///   the device's host API expects, which remains valid for as long as the stream is open.
+    pub unsafe fn open_blocking_stream_raw(
+        &self,
+        in_params: Option<ffi::PaStreamParameters>,
+        out_params: Option<ffi::PaStreamParameters>,
+        sample_rate: f64,
+        frames_per_buffer: u32,
+        flags: StreamFlags,
+    ) -> Result<Stream<Blocking<()>, Raw>, Error> {
+        Stream::open_raw(
+            self.life.clone(),
+            in_params,
+            out_params,
+            sample_rate,
+            frames_per_buffer,
+            flags,
+        )
+    }
+
     /// Open a new non-blocking [**Stream**](./stream/struct.Stream.html) with the given settings.
     ///
     /// When a non-blocking stream is running, PortAudio calls the given `callback` periodically.
@@ -465,6 +1035,128 @@ impl PortAudio {
         Stream::<NonBlocking, S::Flow>::open(self.life.clone(), settings, callback)
     }
 
+    /// Open a new non-blocking **Output** [**Stream**](./stream/struct.Stream.html) that plays
+    /// the interleaved audio submitted via the returned
+    /// [**StreamWriter**](./playback/struct.StreamWriter.html).
+    ///
+    /// The writer copies audio into a ring buffer holding `capacity` frames, which the stream's
+    /// callback drains into each output buffer without allocating or blocking. This allows audio
+    /// to be decoded on another thread. When the writer falls behind, the stream plays silence and
+    /// counts an underflow (see **StreamWriter::underflows**).
+    ///
+    /// Returns `Err(Error::InvalidChannelCount)` if the `settings` have no output channels.
+    ///
+    /// The returned **Stream** is inactive (stopped). See [**playback**](./playback/index.html)
+    /// for details.
+    pub fn open_playback_stream<T>(
+        &self,
+        settings: OutputStreamSettings<T>,
+        capacity: usize,
+    ) -> Result<(PlaybackStream<T>, StreamWriter<T>), Error>
+    where
+        T: Sample + Send + 'static,
+    {
+        playback::open(self.life.clone(), settings, capacity)
+    }
+
+    /// Open and start a blocking input **Stream** with the given `settings` that is read on a
+    /// worker thread, returning a [**CaptureReader**](./capture/struct.CaptureReader.html) from
+    /// which the interleaved audio may be taken on any thread.
+    ///
+    /// The worker copies the audio into a ring buffer holding `capacity` frames. When the reader
+    /// falls behind and the ring is full, the given **OverflowPolicy** decides whether the oldest
+    /// or newest frames are dropped, or whether the worker waits for room.
+    ///
+    /// Returns `Err(Error::InvalidChannelCount)` if the `settings` have no input channels, or
+    /// `Err(Error::BufferTooSmall)` if `capacity` is zero or the `settings` use
+    /// `FRAMES_PER_BUFFER_UNSPECIFIED`, as the worker reads chunks of `frames_per_buffer` frames.
+    ///
+    /// See [**capture**](./capture/index.html) for details.
+    pub fn open_capture<T>(
+        &self,
+        settings: InputStreamSettings<T>,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> Result<CaptureReader<T>, Error>
+    where
+        T: Sample + Send + 'static,
+    {
+        capture::open(self.life.clone(), settings, capacity, policy)
+    }
+
+    /// Play the given interleaved `play_samples` via the `output` while recording `capture_frames`
+    /// frames from the `input`, returning the interleaved recording.
+    ///
+    /// Both directions run on a single blocking **Duplex** stream at the given `sample_rate`, so
+    /// the recording and the playback share a clock. This is the building block for calibration
+    /// tools, e.g. playing a sweep to measure the round-trip latency or frequency response.
+    ///
+    /// The stream runs for the longer of the playback and the capture. If the capture is longer,
+    /// the playback is padded with silence. If it is shorter, the whole of the playback is still
+    /// played but the recording is truncated to `capture_frames`. Input overflow and output
+    /// underflow are tolerated, leaving a gap in the recording or the playback respectively. If
+    /// the `input` has no channels, nothing is recorded and the returned recording is empty.
+    ///
+    /// The stream is opened with clipping and dithering disabled, so that a digital loopback
+    /// returns the played samples unaltered wherever the path allows. The recording may be
+    /// compared with the played signal via a
+    /// [**LoopbackTolerance**](./loopback/enum.Tolerance.html).
+    ///
+    /// Returns `Err(Error::InvalidChannelCount)` if the length of `play_samples` is not a multiple
+    /// of the `output`'s channel count.
+    pub fn capture_and_play(
+        &self,
+        input: StreamParameters<f32>,
+        output: StreamParameters<f32>,
+        sample_rate: f64,
+        play_samples: &[f32],
+        capture_frames: u32,
+    ) -> Result<Vec<f32>, Error> {
+        const FRAMES: u32 = 256;
+        let (in_channels, out_channels) = (input.channel_count, output.channel_count);
+        if out_channels <= 0 {
+            return Err(Error::InvalidChannelCount);
+        }
+        let play_frames = play_samples.len() / out_channels as usize;
+        if play_frames * out_channels as usize != play_samples.len() {
+            return Err(Error::InvalidChannelCount);
+        }
+        let capture_len = capture_frames as usize * std::cmp::max(in_channels, 0) as usize;
+        let total_frames = std::cmp::max(play_frames, capture_frames as usize);
+
+        let flags = stream_flags::CLIP_OFF | stream_flags::DITHER_OFF;
+        let settings = DuplexStreamSettings::with_flags(input, output, sample_rate, FRAMES, flags);
+        let mut stream = self.open_blocking_stream(settings)?;
+        let mut playback = play_samples.chunks(FRAMES as usize * out_channels as usize);
+        let mut captured = Vec::with_capacity(capture_len);
+        stream.start()?;
+        let mut done = 0;
+        while done < total_frames {
+            let frames = std::cmp::min(FRAMES as usize, total_frames - done) as u32;
+            let chunk = playback.next().unwrap_or(&[]);
+            let written = stream.write(frames, |buffer| {
+                let (played, padding) = buffer.split_at_mut(chunk.len());
+                played.copy_from_slice(chunk);
+                for sample in padding {
+                    *sample = 0.0;
+                }
+            });
+            match written {
+                Ok(()) | Err(Error::OutputUnderflowed) => (),
+                Err(err) => return Err(err),
+            }
+            // Without input channels the stream has no input to read.
+            if in_channels > 0 {
+                let (recorded, _overflowed) = stream.read_allowing_overflow(frames)?;
+                let remaining = capture_len - captured.len();
+                captured.extend_from_slice(&recorded[..std::cmp::min(remaining, recorded.len())]);
+            }
+            done += frames as usize;
+        }
+        stream.stop()?;
+        Ok(captured)
+    }
+
     /// Produce the default **StreamParameters** for an **Input** **Stream**.
     ///
     /// The device used will be the default input device for the default Host API.
@@ -598,6 +1290,37 @@ impl PortAudio {
     }
 }
 
+impl Life {
+    /// Claim one of the `max_open_streams` slots for a stream that is about to be opened.
+    ///
+    /// Returns `Err(Error::DeviceUnavailable)` if all slots are taken.
+    fn acquire_stream_slot(&self) -> Result<(), Error> {
+        use std::sync::atomic::Ordering;
+        let max = self.max_open_streams.load(Ordering::SeqCst);
+        let mut open = self.open_streams.load(Ordering::SeqCst);
+        loop {
+            if open >= max {
+                return Err(Error::DeviceUnavailable);
+            }
+            match self.open_streams.compare_exchange(
+                open,
+                open + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(current) => open = current,
+            }
+        }
+    }
+
+    /// Return a slot claimed via `acquire_stream_slot`.
+    fn release_stream_slot(&self) {
+        self.open_streams
+            .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
 impl Drop for Life {
     fn drop(&mut self) {
         if !*self.is_terminated.lock().unwrap() {
@@ -616,6 +1339,21 @@ pub fn version_text() -> Result<&'static str, ::std::str::Utf8Error> {
     unsafe { ffi::c_str_to_str(ffi::Pa_GetVersionText()) }
 }
 
+/// This is used by the **PortAudio::new** and **PortAudio::refresh_devices** methods.
+///
+/// Library initialization function - call this before using PortAudio.
+///
+/// Each successful call must be matched with a corresponding call to terminate().
+fn initialize() -> Result<(), Error> {
+    unsafe {
+        let error = FromPrimitive::from_i32(ffi::Pa_Initialize()).unwrap();
+        match error {
+            Error::NoError => Ok(()),
+            err => Err(err),
+        }
+    }
+}
+
 /// This is used by the **PortAudio::terminate** method.
 ///
 /// Library termination function - call this when finished using PortAudio.
@@ -644,6 +1382,45 @@ fn terminate() -> Result<(), Error> {
     }
 }
 
+/// This is used by the **PortAudio::new_with_alsa_config** method.
+///
+/// Write the given ALSA configuration to a new file in the temporary directory that is only
+/// accessible by the current user, returning its path. The file is created exclusively, so that a
+/// file (or symlink) placed at the same path by another user is never written through.
+#[cfg(target_os = "linux")]
+fn write_private_alsa_config(contents: &str) -> std::io::Result<std::path::PathBuf> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let pid = std::process::id();
+    let mut attempts = 0;
+    loop {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or(0);
+        let name = format!("portaudio-rs-alsa-{}-{:x}{:x}.conf", pid, nanos, attempts);
+        let path = std::env::temp_dir().join(name);
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path);
+        match file {
+            Ok(mut file) => {
+                if let Err(err) = file.write_all(contents.as_bytes()) {
+                    let _ = std::fs::remove_file(&path);
+                    return Err(err);
+                }
+                return Ok(path);
+            }
+            Err(ref err) if err.kind() == std::io::ErrorKind::AlreadyExists && attempts < 16 => {
+                attempts += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
 /// This is used by the **PortAudio::is_*_format_supported** methods.
 ///
 /// Determine whether it would be possible to open a stream with the specified parameters.
@@ -698,6 +1475,18 @@ pub struct Devices<'a> {
     port_audio: &'a PortAudio,
 }
 
+/// An iterator yielding the **DeviceIndex** for each device of a single host API along with their
+/// respective **DeviceInfo**s.
+///
+/// See **PortAudio::host_api_devices**.
+#[derive(Clone, Debug)]
+pub struct HostApiDevices<'a> {
+    host_api: HostApiIndex,
+    total: u32,
+    next: u32,
+    port_audio: &'a PortAudio,
+}
+
 /// An iterator yielding the **HostApiIndex** for each available API along with their respective
 /// **HostApiInfo**s.
 #[derive(Clone, Debug)]
@@ -719,6 +1508,20 @@ impl<'a> Iterator for Devices<'a> {
     }
 }
 
+impl<'a> Iterator for HostApiDevices<'a> {
+    type Item = Result<(DeviceIndex, DeviceInfo<'a>), Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next < self.total {
+            let api_idx = self.next as i32;
+            self.next += 1;
+            let port_audio = self.port_audio;
+            let idx = port_audio.api_device_index_to_device_index(self.host_api, api_idx);
+            return Some(idx.and_then(|idx| port_audio.device_info(idx).map(|info| (idx, info))));
+        }
+        None
+    }
+}
+
 impl<'a> Iterator for HostApis<'a> {
     type Item = (HostApiIndex, HostApiInfo<'a>);
     fn next(&mut self) -> Option<Self::Item> {
@@ -777,6 +1580,11 @@ mod private {
         }
         /// get the sample format
         fn to_sample_format() -> SampleFormat;
+        /// convert the sample to an `f32` where full scale is `-1.0..1.0`
+        fn to_normalised_f32(self) -> f32;
+        /// convert an `f32` where full scale is `-1.0..1.0` to the sample, clamping out of range
+        /// values
+        fn from_normalised_f32(sample: f32) -> Self;
     }
 }
 
@@ -784,42 +1592,176 @@ impl private::SamplePrivate for f32 {
     fn to_sample_format() -> SampleFormat {
         SampleFormat::F32
     }
+    fn to_normalised_f32(self) -> f32 {
+        self
+    }
+    fn from_normalised_f32(sample: f32) -> Self {
+        sample
+    }
 }
 
 impl private::SamplePrivate for i32 {
     fn to_sample_format() -> SampleFormat {
         SampleFormat::I32
     }
+    fn to_normalised_f32(self) -> f32 {
+        (f64::from(self) / 2_147_483_648.0) as f32
+    }
+    fn from_normalised_f32(sample: f32) -> Self {
+        (f64::from(sample) * 2_147_483_648.0)
+            .round()
+            .clamp(-2_147_483_648.0, 2_147_483_647.0) as i32
+    }
 }
 
 impl private::SamplePrivate for i16 {
     fn to_sample_format() -> SampleFormat {
         SampleFormat::I16
     }
+    fn to_normalised_f32(self) -> f32 {
+        f32::from(self) / 32_768.0
+    }
+    fn from_normalised_f32(sample: f32) -> Self {
+        (sample * 32_768.0).round().clamp(-32_768.0, 32_767.0) as i16
+    }
 }
 
 impl private::SamplePrivate for i8 {
     fn to_sample_format() -> SampleFormat {
         SampleFormat::I8
     }
+    fn to_normalised_f32(self) -> f32 {
+        f32::from(self) / 128.0
+    }
+    fn from_normalised_f32(sample: f32) -> Self {
+        (sample * 128.0).round().clamp(-128.0, 127.0) as i8
+    }
 }
 
 impl private::SamplePrivate for u8 {
     fn to_sample_format() -> SampleFormat {
         SampleFormat::U8
     }
+    fn to_normalised_f32(self) -> f32 {
+        (f32::from(self) - 128.0) / 128.0
+    }
+    fn from_normalised_f32(sample: f32) -> Self {
+        (sample * 128.0 + 128.0).round().clamp(0.0, 255.0) as u8
+    }
 }
 
 /// public trait to constraint pa::Stream for specific types
 pub trait Sample: private::SamplePrivate {
+    /// The value of a silent sample, i.e. the midpoint of the type's range.
+    ///
+    /// This is `0` for the signed and floating point types, but `128` for `u8`.
+    const SILENCE: Self;
+
     /// Retrieve the SampleFormat variant associated with the type.
     fn sample_format() -> SampleFormat {
         Self::to_sample_format()
     }
 }
 
-impl Sample for f32 {}
-impl Sample for i32 {}
-impl Sample for i16 {}
-impl Sample for i8 {}
-impl Sample for u8 {}
+impl Sample for f32 {
+    const SILENCE: Self = 0.0;
+}
+impl Sample for i32 {
+    const SILENCE: Self = 0;
+}
+impl Sample for i16 {
+    const SILENCE: Self = 0;
+}
+impl Sample for i8 {
+    const SILENCE: Self = 0;
+}
+impl Sample for u8 {
+    const SILENCE: Self = 128;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_normalisation_round_trips() {
+        use private::SamplePrivate;
+        assert_eq!(
+            i16::from_normalised_f32((-32_768i16).to_normalised_f32()),
+            -32_768
+        );
+        assert_eq!(i16::from_normalised_f32(1.0), 32_767);
+        assert_eq!(i8::from_normalised_f32(0.5), 64);
+        assert_eq!(u8::from_normalised_f32(0.0), 128);
+        assert_eq!(u8::from_normalised_f32(-2.0), 0);
+        assert_eq!(i32::from_normalised_f32(1.0), i32::MAX);
+        assert_eq!(0x4000i16.to_normalised_f32(), 0.5);
+    }
+
+    #[test]
+    fn device_info_out_of_range() {
+        let pa = PortAudio::new().unwrap();
+        let count = pa.device_count().unwrap();
+        assert_eq!(
+            pa.device_info(DeviceIndex(count)),
+            Err(Error::InvalidDevice)
+        );
+        let no_device = DeviceIndex(ffi::PA_NO_DEVICE as u32);
+        assert_eq!(pa.device_info(no_device), Err(Error::InvalidDevice));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn inline_alsa_config_is_written_privately() {
+        use std::os::unix::fs::PermissionsExt;
+        let first = write_private_alsa_config("pcm.!default { type null }").unwrap();
+        let second = write_private_alsa_config("pcm.!default { type null }").unwrap();
+        assert_ne!(first, second);
+        let mode = std::fs::metadata(&first).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        std::fs::remove_file(first).unwrap();
+        std::fs::remove_file(second).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn alsa_config_path_is_restored() {
+        let path = write_private_alsa_config("pcm.!default { type null }").unwrap();
+        std::env::set_var("ALSA_CONFIG_PATH", "/usr/share/alsa/alsa.conf");
+        let _ = PortAudio::new_with_alsa_config(AlsaConfig::Path(&path));
+        assert_eq!(
+            std::env::var_os("ALSA_CONFIG_PATH"),
+            Some("/usr/share/alsa/alsa.conf".into())
+        );
+        std::env::remove_var("ALSA_CONFIG_PATH");
+        let _ = PortAudio::new_with_alsa_config(AlsaConfig::Path(&path));
+        assert_eq!(std::env::var_os("ALSA_CONFIG_PATH"), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn missing_alsa_config_is_rejected() {
+        let path = std::path::Path::new("/nonexistent/portaudio-rs/alsa.conf");
+        match PortAudio::new_with_alsa_config(AlsaConfig::Path(path)) {
+            Err(AlsaConfigError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn max_concurrent_streams_limits_slots() {
+        let pa = PortAudio::new().unwrap();
+        assert_eq!(pa.max_concurrent_streams(), None);
+        pa.set_max_concurrent_streams(Some(1));
+        assert_eq!(pa.max_concurrent_streams(), Some(1));
+        assert_eq!(pa.life.acquire_stream_slot(), Ok(()));
+        assert_eq!(pa.open_stream_count(), 1);
+        assert_eq!(pa.life.acquire_stream_slot(), Err(Error::DeviceUnavailable));
+        pa.life.release_stream_slot();
+        assert_eq!(pa.open_stream_count(), 0);
+        pa.set_max_concurrent_streams(None);
+        assert_eq!(pa.life.acquire_stream_slot(), Ok(()));
+        pa.life.release_stream_slot();
+    }
+}
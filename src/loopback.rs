@@ -0,0 +1,119 @@
+//! Comparison of the signal played and the signal recorded by a loopback test, e.g. via
+//! [**PortAudio::capture_and_play**](../struct.PortAudio.html#method.capture_and_play).
+//!
+//! Whether a recording can be expected to match the played signal exactly depends on the path
+//! between them. A [**Tolerance**](./enum.Tolerance.html) describes how closely the two must
+//! match, and may be chosen from the sample formats of the path via **Tolerance::for_formats**.
+
+use super::types::SampleFormat;
+
+/// The margin in decibels below the theoretical quantisation limit that is still accepted by
+/// **Tolerance::for_formats**, allowing for test signals somewhat below full scale.
+const SNR_MARGIN_DB: f64 = 10.0;
+
+/// How closely a recorded signal must match the played signal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Tolerance {
+    /// Every recorded sample must equal the played sample.
+    BitExact,
+    /// The signal-to-noise ratio of the recording must be at least the given number of decibels,
+    /// where the noise is the difference between the recorded and played signals.
+    MinSnr(f64),
+}
+
+impl Tolerance {
+    /// The tolerance for a digital loopback whose output and input use the given sample formats.
+    ///
+    /// The formats are those used by the device (e.g. as negotiated via
+    /// **PortAudio::open_with_format_fallback**), rather than the `f32` in which samples are
+    /// given to and returned by **PortAudio::capture_and_play**.
+    ///
+    /// - If both sides use `F32`, samples pass through unconverted, so the recording must be
+    ///   **BitExact**. This requires the stream to be opened with dithering and clipping disabled,
+    ///   as **PortAudio::capture_and_play** does.
+    /// - Otherwise each sample is quantised to the narrower of the two formats, so the recording
+    ///   must reach the quantisation limit of that format (6.02 dB per bit plus 1.76 dB for a
+    ///   full-scale sine) less a margin of 10 dB. `F32` and `I32` count as 24 bits, as that is the
+    ///   precision of an `f32` sample, while `Custom` and `Unknown` formats count as 8 bits.
+    ///
+    /// An analog loopback (i.e. a cable from an output to an input) adds noise and changes the
+    /// level of the signal, so it should use a **MinSnr** chosen for the hardware instead.
+    pub fn for_formats(output: SampleFormat, input: SampleFormat) -> Self {
+        if output == SampleFormat::F32 && input == SampleFormat::F32 {
+            return Tolerance::BitExact;
+        }
+        let bits = std::cmp::min(effective_bits(output), effective_bits(input));
+        Tolerance::MinSnr(6.02 * f64::from(bits) + 1.76 - SNR_MARGIN_DB)
+    }
+
+    /// Whether the `recorded` signal matches the `played` signal within this tolerance.
+    ///
+    /// The signals must already be aligned, i.e. any latency must have been trimmed from the start
+    /// of the recording. Only the first `played.len()` samples of the recording are compared, and
+    /// a recording shorter than the played signal never matches.
+    pub fn matches(&self, played: &[f32], recorded: &[f32]) -> bool {
+        if recorded.len() < played.len() {
+            return false;
+        }
+        let recorded = &recorded[..played.len()];
+        match *self {
+            Tolerance::BitExact => played.iter().zip(recorded).all(|(p, r)| p == r),
+            Tolerance::MinSnr(min_db) => snr_db(played, recorded) >= min_db,
+        }
+    }
+}
+
+/// The signal-to-noise ratio in decibels of the `recorded` signal relative to the `played` signal,
+/// where the noise is the difference between the two.
+///
+/// Returns positive infinity if the signals are identical.
+pub fn snr_db(played: &[f32], recorded: &[f32]) -> f64 {
+    let (mut signal, mut noise) = (0.0f64, 0.0f64);
+    for (&p, &r) in played.iter().zip(recorded) {
+        let (p, r) = (f64::from(p), f64::from(r));
+        signal += p * p;
+        noise += (r - p) * (r - p);
+    }
+    if noise == 0.0 {
+        return f64::INFINITY;
+    }
+    10.0 * (signal / noise).log10()
+}
+
+/// The number of bits of precision retained by a sample of the given format.
+fn effective_bits(format: SampleFormat) -> u8 {
+    match format {
+        SampleFormat::F32 | SampleFormat::I32 | SampleFormat::I24 => 24,
+        SampleFormat::I16 => 16,
+        SampleFormat::I8 | SampleFormat::U8 | SampleFormat::Custom | SampleFormat::Unknown => 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quantise(samples: &[f32], bits: i32) -> Vec<f32> {
+        let scale = (1 << (bits - 1)) as f32;
+        samples
+            .iter()
+            .map(|s| (s * scale).round() / scale)
+            .collect()
+    }
+
+    #[test]
+    fn tolerance_follows_the_narrowest_format() {
+        let sine: Vec<f32> = (0..4_800).map(|i| (i as f32 * 0.05).sin() * 0.9).collect();
+        let f32_path = Tolerance::for_formats(SampleFormat::F32, SampleFormat::F32);
+        assert_eq!(f32_path, Tolerance::BitExact);
+        assert!(f32_path.matches(&sine, &sine));
+        assert!(!f32_path.matches(&sine, &quantise(&sine, 24)));
+        assert!(!f32_path.matches(&sine, &sine[1..]));
+
+        let i16_path = Tolerance::for_formats(SampleFormat::F32, SampleFormat::I16);
+        assert!(i16_path.matches(&sine, &quantise(&sine, 16)));
+        assert!(!i16_path.matches(&sine, &quantise(&sine, 8)));
+        let u8_path = Tolerance::for_formats(SampleFormat::U8, SampleFormat::I16);
+        assert!(u8_path.matches(&sine, &quantise(&sine, 8)));
+    }
+}
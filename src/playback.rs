@@ -0,0 +1,563 @@
+//! Output streams that play audio submitted from another thread, e.g. a background decoder.
+//!
+//! A stream opened via
+//! [**PortAudio::open_playback_stream**](../struct.PortAudio.html#method.open_playback_stream) is
+//! paired with a [**StreamWriter**](./struct.StreamWriter.html). The writer copies interleaved
+//! samples into a fixed-size ring buffer, from which the stream's callback copies them into each
+//! output buffer. Neither side allocates or blocks, so the writer may be used from any thread while
+//! the callback runs in its real-time context. When the ring runs dry, the rest of the output
+//! buffer is filled with silence and an underflow is counted.
+//!
+//! With the `varispeed` feature, the speed at which the submitted audio is played may be changed
+//! while the stream is running via **StreamWriter::set_speed**, resampled with the
+//! [**ResampleQuality**](./enum.ResampleQuality.html) chosen via
+//! **StreamWriter::set_resample_quality**. Varispeed is only offered here rather than for every
+//! callback stream: playing faster than real time consumes audio ahead of the current buffer, and
+//! only the ring buffer holds any. A general callback is asked for exactly one buffer at a time and
+//! has nothing further to draw from.
+
+use std::cell::UnsafeCell;
+#[cfg(feature = "varispeed")]
+use std::sync::atomic::{AtomicU32, AtomicU8};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::atomic::AtomicCounter;
+use super::error::Error;
+use super::stream::{NonBlocking, Output, OutputSettings, Stream};
+use super::util::fill_silence;
+use super::{Continue, Life, Sample};
+
+/// The algorithm used to resample the submitted audio when it is played at a speed other than
+/// `1.0` (see **StreamWriter::set_speed**), trading CPU time and latency for fidelity.
+///
+/// Defaults to **ResampleQuality::Balanced**. None of the algorithms are band-limited, so speeding
+/// up always aliases some high frequencies.
+///
+/// Only available with the `varispeed` feature.
+#[cfg(feature = "varispeed")]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ResampleQuality {
+    /// Repeat or skip whole frames. This is the cheapest and adds no latency, but the steps
+    /// between frames are clearly audible as distortion.
+    Fastest,
+    /// Interpolate linearly between neighbouring frames. This is cheap and adds a single frame of
+    /// latency, however it slightly dulls high frequencies.
+    #[default]
+    Balanced,
+    /// Interpolate with a cubic (Catmull-Rom) spline through the four surrounding frames. This
+    /// costs roughly twice as much as **Balanced** and adds two frames of latency, as two frames
+    /// beyond the current position must have been submitted, but preserves high frequencies
+    /// better.
+    Best,
+}
+
+#[cfg(feature = "varispeed")]
+impl ResampleQuality {
+    /// The number of frames beyond the current position that must be available to interpolate.
+    fn lookahead(self) -> usize {
+        match self {
+            ResampleQuality::Fastest => 0,
+            ResampleQuality::Balanced => 1,
+            ResampleQuality::Best => 2,
+        }
+    }
+
+    /// The number of frames before the current position that are kept in the ring to interpolate.
+    fn history(self) -> usize {
+        match self {
+            ResampleQuality::Best => 1,
+            ResampleQuality::Fastest | ResampleQuality::Balanced => 0,
+        }
+    }
+
+    /// Interpolate at `fraction` between the frames `at[1]` and `at[2]`, where `at[0]` and `at[3]`
+    /// are the frames before and after those.
+    fn interpolate(self, at: [f32; 4], fraction: f32) -> f32 {
+        match self {
+            ResampleQuality::Fastest => at[1],
+            ResampleQuality::Balanced => at[1] + (at[2] - at[1]) * fraction,
+            ResampleQuality::Best => {
+                let a = -0.5 * at[0] + 1.5 * at[1] - 1.5 * at[2] + 0.5 * at[3];
+                let b = at[0] - 2.5 * at[1] + 2.0 * at[2] - 0.5 * at[3];
+                let c = -0.5 * at[0] + 0.5 * at[2];
+                ((a * fraction + b) * fraction + c) * fraction + at[1]
+            }
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => ResampleQuality::Fastest,
+            2 => ResampleQuality::Best,
+            _ => ResampleQuality::Balanced,
+        }
+    }
+}
+
+/// A single-producer, single-consumer ring buffer of interleaved samples.
+struct Ring<T> {
+    /// The storage, whose length is a power of two so that the positions below map to the same
+    /// index before and after they wrap around `usize::MAX`.
+    samples: Box<[UnsafeCell<T>]>,
+    /// The maximum number of samples held by the ring, which may be less than `samples.len()`.
+    capacity: usize,
+    /// The total number of samples ever read, advanced only by the consumer.
+    read: AtomicUsize,
+    /// The total number of samples ever written, advanced only by the producer.
+    written: AtomicUsize,
+    /// The number of times the consumer ran out of samples.
+    underflows: AtomicCounter,
+    /// The playback speed as `f32` bits, set by **StreamWriter::set_speed**.
+    #[cfg(feature = "varispeed")]
+    speed: AtomicU32,
+    /// The **ResampleQuality** as `u8`, set by **StreamWriter::set_resample_quality**.
+    #[cfg(feature = "varispeed")]
+    quality: AtomicU8,
+}
+
+// Each sample is only ever accessed by one of the producer and consumer at a time, as determined
+// by the `read` and `written` positions.
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T: Sample> Ring<T> {
+    /// A ring with room for the given number of samples.
+    ///
+    /// Returns `None` if the storage for the samples cannot be sized.
+    fn new(capacity: usize) -> Option<Self> {
+        let len = capacity.checked_next_power_of_two()?;
+        Some(Ring {
+            samples: (0..len).map(|_| UnsafeCell::new(T::SILENCE)).collect(),
+            capacity: capacity,
+            read: AtomicUsize::new(0),
+            written: AtomicUsize::new(0),
+            underflows: AtomicCounter::new(0),
+            #[cfg(feature = "varispeed")]
+            speed: AtomicU32::new(1.0f32.to_bits()),
+            #[cfg(feature = "varispeed")]
+            quality: AtomicU8::new(ResampleQuality::default() as u8),
+        })
+    }
+
+    /// The index within `samples` of the given position.
+    fn index(&self, position: usize) -> usize {
+        position & (self.samples.len() - 1)
+    }
+
+    /// The number of samples that have been written but not yet read.
+    fn len(&self) -> usize {
+        let written = self.written.load(Ordering::Acquire);
+        written.wrapping_sub(self.read.load(Ordering::Acquire))
+    }
+
+    /// Copy as many of the given samples as fit into the ring, rounded down to a multiple of
+    /// `granule`, returning the number copied.
+    ///
+    /// Must only be called by the producer.
+    fn push(&self, samples: &[T], granule: usize) -> usize {
+        let written = self.written.load(Ordering::Relaxed);
+        let read = self.read.load(Ordering::Acquire);
+        let free = self.capacity - written.wrapping_sub(read);
+        let count = free.min(samples.len()) / granule * granule;
+        for (i, &sample) in samples[..count].iter().enumerate() {
+            let idx = self.index(written.wrapping_add(i));
+            unsafe { *self.samples[idx].get() = sample };
+        }
+        self.written
+            .store(written.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Fill the given buffer from the ring, filling any remainder with silence and counting an
+    /// underflow if the ring runs dry.
+    ///
+    /// Must only be called by the consumer.
+    fn pop(&self, buffer: &mut [T]) {
+        let read = self.read.load(Ordering::Relaxed);
+        let written = self.written.load(Ordering::Acquire);
+        let count = written.wrapping_sub(read).min(buffer.len());
+        for (i, sample) in buffer[..count].iter_mut().enumerate() {
+            let idx = self.index(read.wrapping_add(i));
+            *sample = unsafe { *self.samples[idx].get() };
+        }
+        self.read.store(read.wrapping_add(count), Ordering::Release);
+        if count < buffer.len() {
+            fill_silence(&mut buffer[count..]);
+            self.underflows.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The playback speed set via **StreamWriter::set_speed**.
+    #[cfg(feature = "varispeed")]
+    fn speed(&self) -> f64 {
+        f64::from(f32::from_bits(self.speed.load(Ordering::Relaxed)))
+    }
+
+    /// Fill the given buffer of interleaved frames at the speed and with the quality set via the
+    /// **StreamWriter**, where `phase` is the fractional frame position carried between buffers.
+    ///
+    /// Frames are only copied directly at a speed of `1.0` once the position lies on a frame.
+    /// Returning to `1.0` part way between frames keeps interpolating at that offset instead, and
+    /// any frames kept for interpolation have already been played, so they are skipped. Either way
+    /// the output continues from exactly where the resampled output left off.
+    ///
+    /// Must only be called by the consumer.
+    #[cfg(feature = "varispeed")]
+    fn pop_at_speed(&self, buffer: &mut [T], channels: usize, phase: &mut f64) {
+        let speed = self.speed();
+        if speed != 1.0 || phase.fract() != 0.0 {
+            self.pop_varispeed(buffer, channels, speed, self.quality(), phase);
+            return;
+        }
+        if *phase >= 1.0 {
+            self.skip(*phase as usize * channels);
+            *phase = 0.0;
+        }
+        self.pop(buffer);
+    }
+
+    /// Discard up to `count` samples from the ring.
+    ///
+    /// Must only be called by the consumer.
+    #[cfg(feature = "varispeed")]
+    fn skip(&self, count: usize) {
+        let read = self.read.load(Ordering::Relaxed);
+        let written = self.written.load(Ordering::Acquire);
+        let count = written.wrapping_sub(read).min(count);
+        self.read.store(read.wrapping_add(count), Ordering::Release);
+    }
+
+    /// The resampling algorithm set via **StreamWriter::set_resample_quality**.
+    #[cfg(feature = "varispeed")]
+    fn quality(&self) -> ResampleQuality {
+        ResampleQuality::from_u8(self.quality.load(Ordering::Relaxed))
+    }
+
+    /// Fill the given buffer of interleaved frames by resampling the ring's frames at the given
+    /// `speed` with the given `quality`, starting at the fractional frame `phase`, which is updated
+    /// for the next buffer.
+    ///
+    /// The frames that `quality` interpolates beyond the current position must always be
+    /// available. The remainder is filled with silence and an underflow counted otherwise. The
+    /// frames it interpolates before the current position are left in the ring for the next
+    /// buffer, so `phase` may exceed `1.0`.
+    ///
+    /// Must only be called by the consumer.
+    #[cfg(feature = "varispeed")]
+    fn pop_varispeed(
+        &self,
+        buffer: &mut [T],
+        channels: usize,
+        speed: f64,
+        quality: ResampleQuality,
+        phase: &mut f64,
+    ) {
+        let read = self.read.load(Ordering::Relaxed);
+        let written = self.written.load(Ordering::Acquire);
+        let available = written.wrapping_sub(read) / channels;
+        let sample_at = |frame: usize, channel: usize| {
+            let frame = std::cmp::min(frame, available - 1);
+            let idx = self.index(read.wrapping_add(frame * channels + channel));
+            unsafe { *self.samples[idx].get() }.to_normalised_f32()
+        };
+        let mut position = *phase;
+        let mut filled = 0;
+        for frame in buffer.chunks_mut(channels) {
+            let idx = position as usize;
+            if idx + quality.lookahead() >= available {
+                break;
+            }
+            let fraction = (position - idx as f64) as f32;
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let at = [
+                    sample_at(idx.saturating_sub(1), channel),
+                    sample_at(idx, channel),
+                    sample_at(idx + 1, channel),
+                    sample_at(idx + 2, channel),
+                ];
+                *sample = T::from_normalised_f32(quality.interpolate(at, fraction));
+            }
+            position += speed;
+            filled += frame.len();
+        }
+        let consumed =
+            std::cmp::min(position as usize, available).saturating_sub(quality.history());
+        *phase = position - consumed as f64;
+        self.read
+            .store(read.wrapping_add(consumed * channels), Ordering::Release);
+        if filled < buffer.len() {
+            fill_silence(&mut buffer[filled..]);
+            self.underflows.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A stream opened via
+/// [**PortAudio::open_playback_stream**](../struct.PortAudio.html#method.open_playback_stream).
+pub type PlaybackStream<T> = Stream<NonBlocking, Output<T>>;
+
+/// A handle for submitting interleaved audio to a stream opened via
+/// [**PortAudio::open_playback_stream**](../struct.PortAudio.html#method.open_playback_stream).
+///
+/// There is exactly one writer per stream. It may be sent to another thread, such as the one
+/// decoding the audio.
+pub struct StreamWriter<T> {
+    ring: Arc<Ring<T>>,
+    channels: usize,
+}
+
+impl<T: Sample> StreamWriter<T> {
+    /// Copy as many whole frames of the given interleaved `samples` into the stream's ring buffer
+    /// as there is room for, returning the number of frames copied.
+    ///
+    /// This never blocks. Any frames that are not copied should be submitted again once the stream
+    /// has played some of the queued audio (see **StreamWriter::fill_level**).
+    pub fn submit(&mut self, samples: &[T]) -> usize {
+        self.ring.push(samples, self.channels) / self.channels
+    }
+
+    /// The number of frames that are queued in the ring buffer but have not yet been played.
+    ///
+    /// A decoder can pace itself by keeping this above the number of frames played during the time
+    /// it takes to decode the next block of audio.
+    pub fn fill_level(&self) -> usize {
+        self.ring.len() / self.channels
+    }
+
+    /// The number of frames that the ring buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity / self.channels
+    }
+
+    /// The number of callbacks in which the ring buffer ran dry before the output buffer was
+    /// filled, meaning that the writer fell behind the stream.
+    ///
+    /// This includes any callbacks that run before the first frames are submitted.
+    pub fn underflows(&self) -> u64 {
+        self.ring.underflows.load(Ordering::Relaxed)
+    }
+
+    /// Play the submitted audio at the given speed `ratio` relative to the stream's sample rate,
+    /// changing its pitch along with its speed (varispeed).
+    ///
+    /// A `ratio` of `1.0` (the default) plays the audio unaltered, `2.0` plays it twice as fast and
+    /// an octave higher and `0.5` half as fast and an octave lower. The new speed takes effect from
+    /// the next callback. The ring buffer drains `ratio` times as fast as at normal speed, so the
+    /// writer must submit audio correspondingly faster to avoid underflows.
+    ///
+    /// At any speed other than `1.0`, the stream's callback resamples the audio with the algorithm
+    /// chosen via **StreamWriter::set_resample_quality**, linear interpolation by default. None of
+    /// the algorithms are band-limited, so varispeed suits effects and scrubbing rather than
+    /// high-fidelity sample rate conversion.
+    ///
+    /// The `ratio` is stored with single precision, so that it may be shared with the callback
+    /// atomically on all targets.
+    ///
+    /// Returns `Err(Error::InvalidSampleRate)` if `ratio` is not a positive, finite number.
+    ///
+    /// Only available with the `varispeed` feature.
+    #[cfg(feature = "varispeed")]
+    pub fn set_speed(&self, ratio: f64) -> Result<(), Error> {
+        let ratio = ratio as f32;
+        if !(ratio > 0.0 && ratio.is_finite()) {
+            return Err(Error::InvalidSampleRate);
+        }
+        self.ring.speed.store(ratio.to_bits(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The playback speed set via **StreamWriter::set_speed**.
+    ///
+    /// Only available with the `varispeed` feature.
+    #[cfg(feature = "varispeed")]
+    pub fn speed(&self) -> f64 {
+        self.ring.speed()
+    }
+
+    /// Resample the audio played at a speed other than `1.0` with the given `quality`, taking
+    /// effect from the next callback. See **ResampleQuality** for the trade-offs.
+    ///
+    /// Only available with the `varispeed` feature.
+    #[cfg(feature = "varispeed")]
+    pub fn set_resample_quality(&self, quality: ResampleQuality) {
+        self.ring.quality.store(quality as u8, Ordering::Relaxed);
+    }
+
+    /// The resampling algorithm set via **StreamWriter::set_resample_quality**.
+    ///
+    /// Only available with the `varispeed` feature.
+    #[cfg(feature = "varispeed")]
+    pub fn resample_quality(&self) -> ResampleQuality {
+        self.ring.quality()
+    }
+}
+
+/// Open a non-blocking **Output** stream that plays the audio submitted via the returned
+/// **StreamWriter**, whose ring buffer holds `capacity` frames.
+pub fn open<T>(
+    life: Arc<Life>,
+    settings: OutputSettings<T>,
+    capacity: usize,
+) -> Result<(PlaybackStream<T>, StreamWriter<T>), Error>
+where
+    T: Sample + Send + 'static,
+{
+    if settings.params.channel_count <= 0 {
+        return Err(Error::InvalidChannelCount);
+    }
+    let channels = settings.params.channel_count as usize;
+    let samples = capacity.checked_mul(channels).ok_or(Error::BufferTooBig)?;
+    let ring = Arc::new(Ring::new(samples).ok_or(Error::BufferTooBig)?);
+    let consumer = ring.clone();
+    #[cfg(feature = "varispeed")]
+    let mut phase = 0.0;
+    let callback = move |args: super::OutputStreamCallbackArgs<T>| {
+        #[cfg(feature = "varispeed")]
+        consumer.pop_at_speed(args.buffer, channels, &mut phase);
+        #[cfg(not(feature = "varispeed"))]
+        consumer.pop(args.buffer);
+        Continue
+    };
+    let stream = Stream::<NonBlocking, Output<T>>::open(life, settings, callback)?;
+    let writer = StreamWriter {
+        ring: ring,
+        channels: channels,
+    };
+    Ok((stream, writer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_wraps_and_counts_underflows() {
+        let ring = Ring::<i16>::new(6).unwrap();
+        assert_eq!(ring.push(&[1, 2, 3, 4, 5], 2), 4);
+        let mut buffer = [0; 2];
+        ring.pop(&mut buffer);
+        assert_eq!(buffer, [1, 2]);
+
+        // The writes wrap around the end of the ring.
+        assert_eq!(ring.push(&[5, 6, 7, 8, 9, 10], 2), 4);
+        assert_eq!(ring.len(), 6);
+        let mut buffer = [0; 6];
+        ring.pop(&mut buffer);
+        assert_eq!(buffer, [3, 4, 5, 6, 7, 8]);
+        assert_eq!(ring.underflows.load(Ordering::Relaxed), 0);
+
+        ring.push(&[11, 12], 2);
+        let mut buffer = [-1; 4];
+        ring.pop(&mut buffer);
+        assert_eq!(buffer, [11, 12, 0, 0]);
+        assert_eq!(ring.underflows.load(Ordering::Relaxed), 1);
+        assert_eq!(ring.len(), 0);
+    }
+
+    #[test]
+    fn ring_drains_across_counter_overflow() {
+        let ring = Ring::<i16>::new(6).unwrap();
+        // Start just short of the positions wrapping, where `usize::MAX + 1` is not a multiple of
+        // the capacity.
+        ring.read.store(usize::MAX - 3, Ordering::Relaxed);
+        ring.written.store(usize::MAX - 3, Ordering::Relaxed);
+        let mut next = 0;
+        let mut expected = 0;
+        for _ in 0..8 {
+            let samples = [next, next + 1, next + 2, next + 3];
+            assert_eq!(ring.push(&samples, 2), 4);
+            next += 4;
+            // Drain the ring as the stream's callback would, in buffers of two frames.
+            while ring.len() > 0 {
+                let mut buffer = [0; 2];
+                ring.pop(&mut buffer);
+                assert_eq!(buffer, [expected, expected + 1]);
+                expected += 2;
+            }
+        }
+        assert_eq!(expected, next);
+        assert_eq!(ring.underflows.load(Ordering::Relaxed), 0);
+    }
+
+    #[cfg(feature = "varispeed")]
+    #[test]
+    fn varispeed_interpolates_frames() {
+        let ring = Ring::<f32>::new(8).unwrap();
+        ring.push(&[0.0, 0.25, 0.5, 0.75], 1);
+        let mut phase = 0.0;
+        let mut buffer = [1.0; 4];
+        ring.pop_varispeed(&mut buffer, 1, 0.5, ResampleQuality::Balanced, &mut phase);
+        assert_eq!(buffer, [0.0, 0.125, 0.25, 0.375]);
+        assert_eq!((ring.len(), phase), (2, 0.0));
+
+        // Only one more frame can be interpolated before the ring runs dry.
+        let mut buffer = [1.0; 2];
+        ring.pop_varispeed(&mut buffer, 1, 2.0, ResampleQuality::Balanced, &mut phase);
+        assert_eq!(buffer, [0.5, 0.0]);
+        assert_eq!(ring.underflows.load(Ordering::Relaxed), 1);
+    }
+
+    #[cfg(feature = "varispeed")]
+    #[test]
+    fn resample_quality_chooses_the_interpolation() {
+        let frames = [0.0, 0.5, 0.0, -0.5, 0.0, 0.5];
+        let resample = |quality| {
+            let ring = Ring::<f32>::new(8).unwrap();
+            ring.push(&frames, 1);
+            let mut phase = 0.0;
+            let mut buffer = [1.0; 4];
+            ring.pop_varispeed(&mut buffer, 1, 0.5, quality, &mut phase);
+            (buffer, ring.len(), phase)
+        };
+        assert_eq!(
+            resample(ResampleQuality::Fastest),
+            ([0.0, 0.0, 0.5, 0.5], 4, 0.0)
+        );
+        assert_eq!(
+            resample(ResampleQuality::Balanced),
+            ([0.0, 0.25, 0.5, 0.25], 4, 0.0)
+        );
+        // The spline overshoots the linear interpolation and keeps the frame before its position.
+        // There is no frame before the first, which is used in its place.
+        assert_eq!(
+            resample(ResampleQuality::Best),
+            ([0.0, 0.28125, 0.5, 0.3125], 5, 1.0)
+        );
+    }
+
+    #[cfg(feature = "varispeed")]
+    #[test]
+    fn returning_to_normal_speed_continues_the_output() {
+        // A ramp rising by one step per frame, which each quality reproduces exactly once away
+        // from its start.
+        let step = 1.0 / 64.0;
+        let ramp: Vec<f32> = (0..48).map(|frame| frame as f32 * step).collect();
+        for &quality in &[ResampleQuality::Balanced, ResampleQuality::Best] {
+            // 0.5 leaves the position on a frame and 0.7 part way between two.
+            for &speed in &[0.5, 0.7] {
+                let ring = Ring::<f32>::new(64).unwrap();
+                ring.push(&ramp, 1);
+                ring.speed
+                    .store((speed as f32).to_bits(), Ordering::Relaxed);
+                ring.quality.store(quality as u8, Ordering::Relaxed);
+                let mut phase = 0.0;
+                let mut slow = [0.0; 8];
+                ring.pop_at_speed(&mut slow, 1, &mut phase);
+                ring.speed.store(1.0f32.to_bits(), Ordering::Relaxed);
+                let mut normal = [0.0; 8];
+                ring.pop_at_speed(&mut normal, 1, &mut phase);
+                let expected = slow[7] + (speed as f32 * step);
+                assert!(
+                    (normal[0] - expected).abs() < 1e-6,
+                    "{:?} at {}",
+                    quality,
+                    speed
+                );
+                for pair in normal.windows(2) {
+                    assert!((pair[1] - pair[0] - step).abs() < 1e-6);
+                }
+                assert_eq!(ring.underflows.load(Ordering::Relaxed), 0);
+            }
+        }
+    }
+}
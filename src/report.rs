@@ -0,0 +1,259 @@
+//! A machine-readable summary of the audio setup of the machine, intended for bug reports.
+//!
+//! See [**PortAudio::probe**](../struct.PortAudio.html#method.probe).
+
+use super::types::{DeviceIndex, HostApiIndex, HostApiTypeId, StableDeviceId, Time};
+
+/// The sample rates that are checked for each device by **PortAudio::probe**.
+pub const STANDARD_SAMPLE_RATES: [f64; 13] = [
+    8000.0, 9600.0, 11025.0, 12000.0, 16000.0, 22050.0, 24000.0, 32000.0, 44100.0, 48000.0,
+    88200.0, 96000.0, 192000.0,
+];
+
+/// Everything PortAudio knows about the audio setup of the machine.
+///
+/// Produced by **PortAudio::probe**. With the `serde` feature enabled the report may be serialized,
+/// e.g. to a JSON document that may be attached to an issue.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AudioReport {
+    /// The PortAudio version number. See **PortAudio::version**.
+    pub version: i32,
+    /// The PortAudio version text. See **PortAudio::version_text**.
+    pub version_text: String,
+    /// The default host API, if any.
+    pub default_host_api: Option<HostApiIndex>,
+    /// The default input device of the default host API, if any.
+    pub default_input_device: Option<DeviceIndex>,
+    /// The default output device of the default host API, if any.
+    pub default_output_device: Option<DeviceIndex>,
+    /// All available host APIs.
+    pub host_apis: Vec<HostApiReport>,
+    /// All available devices.
+    pub devices: Vec<DeviceReport>,
+}
+
+/// A summary of a single host API within an **AudioReport**.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HostApiReport {
+    /// The index of the host API.
+    pub index: HostApiIndex,
+    /// The type of the host API.
+    pub host_type: HostApiTypeId,
+    /// The name of the host API.
+    pub name: String,
+    /// The number of devices belonging to the host API.
+    pub device_count: u32,
+    /// The host API's default input device, if any.
+    pub default_input_device: Option<DeviceIndex>,
+    /// The host API's default output device, if any.
+    pub default_output_device: Option<DeviceIndex>,
+}
+
+/// A summary of a single device and its capabilities within an **AudioReport**.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DeviceReport {
+    /// The index of the device.
+    pub index: DeviceIndex,
+    /// The stable identifier of the device. See **DeviceInfo::stable_id**.
+    pub stable_id: StableDeviceId,
+    /// The name of the device.
+    pub name: String,
+    /// The host API to which the device belongs.
+    pub host_api: HostApiIndex,
+    /// The maximum number of input channels.
+    pub max_input_channels: i32,
+    /// The maximum number of output channels.
+    pub max_output_channels: i32,
+    /// The default low input latency.
+    pub default_low_input_latency: Time,
+    /// The default low output latency.
+    pub default_low_output_latency: Time,
+    /// The default high input latency.
+    pub default_high_input_latency: Time,
+    /// The default high output latency.
+    pub default_high_output_latency: Time,
+    /// The default sample rate.
+    pub default_sample_rate: f64,
+    /// The **STANDARD_SAMPLE_RATES** supported for `f32` input using all input channels.
+    pub input_sample_rates: Vec<f64>,
+    /// The **STANDARD_SAMPLE_RATES** supported for `f32` output using all output channels.
+    pub output_sample_rates: Vec<f64>,
+}
+
+/// Details of the PortAudio build, extracted from its version information on a best-effort basis.
+///
+/// Produced by **PortAudio::build_info**. The version text has no fixed format and differs
+/// between PortAudio releases (e.g. `PortAudio V19.7.0-devel, revision 147dd72` or
+/// `PortAudio V19-devel (built Feb 25 2014 21:09:53)`), so each field is `None` unless a
+/// recognisable token for it is found.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BuildInfo {
+    /// The major, minor and sub-minor version numbers, e.g. `(19, 7, 0)`.
+    pub version: Option<(i32, i32, i32)>,
+    /// The pre-release label following the version number, e.g. `devel`.
+    pub pre_release: Option<String>,
+    /// The version control revision from which PortAudio was built.
+    pub revision: Option<String>,
+    /// The date and time at which PortAudio was built, as given in the version text.
+    pub build_date: Option<String>,
+    /// The host APIs named in the version text, in order of appearance.
+    ///
+    /// Official builds do not list their host APIs, in which case this is `None`. Use
+    /// **PortAudio::host_apis** to find the host APIs that are actually available.
+    pub host_apis: Option<Vec<HostApiTypeId>>,
+}
+
+/// Names by which host APIs may be referred to in the version text, in upper case.
+const HOST_API_NAMES: &[(&str, HostApiTypeId)] = &[
+    ("ALSA", HostApiTypeId::ALSA),
+    ("JACK", HostApiTypeId::JACK),
+    ("OSS", HostApiTypeId::OSS),
+    ("ASIHPI", HostApiTypeId::AudioScienceHPI),
+    ("ASIO", HostApiTypeId::ASIO),
+    ("WASAPI", HostApiTypeId::WASAPI),
+    ("WDMKS", HostApiTypeId::WDMKS),
+    ("WDM-KS", HostApiTypeId::WDMKS),
+    ("DS", HostApiTypeId::DirectSound),
+    ("DSOUND", HostApiTypeId::DirectSound),
+    ("DIRECTSOUND", HostApiTypeId::DirectSound),
+    ("MME", HostApiTypeId::MME),
+    ("WMME", HostApiTypeId::MME),
+    ("COREAUDIO", HostApiTypeId::CoreAudio),
+];
+
+impl BuildInfo {
+    /// Extract the recognisable tokens from the given version text.
+    ///
+    /// Unrecognised text is ignored, so this never fails.
+    pub fn parse(version_text: &str) -> Self {
+        let words: Vec<&str> = version_text
+            .split(|c: char| c.is_whitespace() || c == ',' || c == ';' || c == '(' || c == ')')
+            .filter(|word| !word.is_empty())
+            .collect();
+        let mut info = BuildInfo::default();
+        for (i, word) in words.iter().enumerate() {
+            let upper = word.to_uppercase();
+            if info.version.is_none() {
+                if let Some((version, pre_release)) = parse_version(word) {
+                    info.version = Some(version);
+                    info.pre_release = pre_release;
+                    continue;
+                }
+            }
+            if upper == "REVISION" || upper == "REV" {
+                if let Some(revision) = words.get(i + 1) {
+                    info.revision = Some(revision.trim_end_matches(':').to_string());
+                }
+                continue;
+            }
+            let host_api = HOST_API_NAMES
+                .iter()
+                .find(|&&(name, _)| name == upper)
+                .map(|&(_, host_api)| host_api);
+            if let Some(host_api) = host_api {
+                let host_apis = info.host_apis.get_or_insert_with(Vec::new);
+                if !host_apis.contains(&host_api) {
+                    host_apis.push(host_api);
+                }
+            }
+        }
+        info.build_date = parse_build_date(version_text);
+        info
+    }
+}
+
+/// Parse a version token such as `V19.7.0-devel`, returning the version numbers along with any
+/// pre-release label. Missing minor and sub-minor numbers are taken to be `0`.
+fn parse_version(word: &str) -> Option<((i32, i32, i32), Option<String>)> {
+    let word = word.trim_start_matches(['V', 'v']);
+    if !word.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut parts = word.splitn(2, '-');
+    let numbers = parts.next().unwrap_or("");
+    let pre_release = parts.next().filter(|label| !label.is_empty());
+    let mut numbers = numbers.split('.').map(|n| n.parse::<i32>());
+    let major = numbers.next()?.ok()?;
+    let minor = numbers.next().unwrap_or(Ok(0)).ok()?;
+    let sub_minor = numbers.next().unwrap_or(Ok(0)).ok()?;
+    Some(((major, minor, sub_minor), pre_release.map(str::to_string)))
+}
+
+/// The text following `built` within the parentheses of the version text, if any.
+fn parse_build_date(version_text: &str) -> Option<String> {
+    let start = version_text.find("(built ")? + "(built ".len();
+    let rest = &version_text[start..];
+    let date = &rest[..rest.find(')').unwrap_or(rest.len())];
+    Some(date.trim().to_string()).filter(|date| !date.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn report_serializes_to_json() {
+        extern crate serde_json;
+        let report = AudioReport {
+            version: 1899,
+            version_text: "PortAudio V19".to_string(),
+            default_host_api: Some(0),
+            default_input_device: None,
+            default_output_device: Some(DeviceIndex(0)),
+            host_apis: vec![],
+            devices: vec![DeviceReport {
+                index: DeviceIndex(0),
+                stable_id: StableDeviceId(0xff),
+                name: "\"Speakers\"\n".to_string(),
+                host_api: 0,
+                max_input_channels: 0,
+                max_output_channels: 2,
+                default_low_input_latency: -1.0,
+                default_low_output_latency: 0.01,
+                default_high_input_latency: -1.0,
+                default_high_output_latency: 0.1,
+                default_sample_rate: 44100.0,
+                input_sample_rates: vec![],
+                output_sample_rates: vec![44100.0, 48000.0],
+            }],
+        };
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["version_text"], "PortAudio V19");
+        assert!(json["default_input_device"].is_null());
+        assert_eq!(json["host_apis"], serde_json::json!([]));
+        assert_eq!(json["devices"][0]["stable_id"], 0xff);
+        assert_eq!(json["devices"][0]["name"], "\"Speakers\"\n");
+        let rates = &json["devices"][0]["output_sample_rates"];
+        assert_eq!(*rates, serde_json::json!([44100.0, 48000.0]));
+    }
+
+    #[test]
+    fn build_info_parses_known_formats() {
+        let info =
+            BuildInfo::parse("PortAudio V19.7.0-devel, revision 147dd722548358763a8b649b3e4b");
+        assert_eq!(info.version, Some((19, 7, 0)));
+        assert_eq!(info.pre_release, Some("devel".to_string()));
+        assert_eq!(
+            info.revision,
+            Some("147dd722548358763a8b649b3e4b".to_string())
+        );
+        assert_eq!(info.build_date, None);
+        assert_eq!(info.host_apis, None);
+
+        let info = BuildInfo::parse("PortAudio V19-devel (built Feb 25 2014 21:09:53)");
+        assert_eq!(info.version, Some((19, 0, 0)));
+        assert_eq!(info.build_date, Some("Feb 25 2014 21:09:53".to_string()));
+        assert_eq!(info.revision, None);
+
+        let info = BuildInfo::parse("custom build with ALSA, JACK and alsa");
+        assert_eq!(info.version, None);
+        let host_apis = vec![HostApiTypeId::ALSA, HostApiTypeId::JACK];
+        assert_eq!(info.host_apis, Some(host_apis));
+
+        assert_eq!(BuildInfo::parse(""), BuildInfo::default());
+    }
+}
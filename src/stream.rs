@@ -7,12 +7,16 @@ use ffi;
 use libc;
 use num::FromPrimitive;
 use std::os::raw;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::{self, ptr};
 
+use super::atomic::AtomicCounter;
 use super::error::Error;
 use super::types::{
     sample_format_flags, DeviceIndex, DeviceKind, SampleFormat, SampleFormatFlags, Time,
 };
+use super::util::fill_silence;
 use super::Sample;
 
 pub use self::callback_flags::CallbackFlags;
@@ -43,7 +47,9 @@ pub trait Flow {
     /// Timing information for the buffer passed to the stream callback.
     type CallbackTimeInfo;
     /// Construct a new **Self::Buffer**.
-    fn new_buffer(&self, frames_per_buffer: u32) -> Self::Buffer;
+    ///
+    /// Returns `Err(Error::BufferTooBig)` if the size of the buffer would overflow `usize`.
+    fn new_buffer(&self, frames_per_buffer: u32) -> Result<Self::Buffer, Error>;
     /// Necessary for dynamically acquiring bi-directional params for Pa_OpenStream.
     fn params_both_directions(
         &self,
@@ -62,6 +68,10 @@ pub trait Flow {
         in_channels: i32,
         out_channels: i32,
     ) -> Self::CallbackArgs;
+    /// The same **Flow** with any host API specific stream info removed from its **Parameters**.
+    ///
+    /// Used when reopening a stream, as the structure is not guaranteed to outlive the original.
+    fn without_host_api_specific_stream_info(self) -> Self;
 }
 
 /// **Streams** that can be read by the user.
@@ -91,11 +101,731 @@ type CallbackFn = FnMut(
     raw::c_ulong,
     *const ffi::PaStreamCallbackTimeInfo,
     ffi::PaStreamCallbackFlags,
+    i32,
+    i32,
 ) -> ffi::PaStreamCallbackResult;
 
 /// A wrapper around a user-given **CallbackFn** that can be sent to PortAudio.
 struct CallbackFnWrapper {
     f: Box<CallbackFn>,
+    /// The number of input channels of the currently open stream.
+    in_channels: i32,
+    /// The number of output channels of the currently open stream.
+    out_channels: i32,
+    /// The sample format of the input buffer of the currently open stream.
+    in_format: SampleFormatFlags,
+    /// The sample format of the output buffer of the currently open stream.
+    out_format: SampleFormatFlags,
+    /// State shared with the owning **NonBlocking** stream.
+    state: Arc<CallbackState>,
+    /// The owning stream's frame totals.
+    frame_totals: Arc<FrameTotals>,
+    /// One flag per output channel of the currently open stream, set by **Stream::set_channel_mute**.
+    output_mutes: Arc<Vec<AtomicBool>>,
+    /// Whether or not the most recent callback produced silence due to the stream being paused.
+    ///
+    /// Only accessed from the audio thread.
+    is_paused: bool,
+    /// The de-click fade that is currently being applied to the output, if any.
+    ///
+    /// Only accessed from the audio thread.
+    fade: Option<Fade>,
+}
+
+/// A linear fade of the output gain over a number of frames, spanning one or more callbacks.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Fade {
+    from: f32,
+    to: f32,
+    /// The number of frames of the fade that have been applied so far.
+    position: u32,
+    frames: u32,
+}
+
+/// The signal level of a single block of audio passed to a **NonBlocking** stream's callback.
+///
+/// Both values are normalised so that `1.0` represents full scale.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Level {
+    /// The root mean square of all samples in the block, across all channels.
+    pub rms: f32,
+    /// The greatest absolute sample value in the block, across all channels.
+    pub peak: f32,
+}
+
+/// The most recent **Level** of a single direction of a stream.
+///
+/// The values are stored as `f32` bits so that they may be written from the audio thread without
+/// locking.
+#[derive(Default)]
+struct LevelMeter {
+    enabled: AtomicBool,
+    rms: AtomicU32,
+    peak: AtomicU32,
+}
+
+/// The input and output **LevelMeter**s of a **NonBlocking** stream.
+#[derive(Default)]
+struct Meters {
+    input: LevelMeter,
+    output: LevelMeter,
+}
+
+/// The number of times that the callback was invoked along with the xruns reported to it.
+#[derive(Default)]
+struct Counters {
+    callbacks: AtomicCounter,
+    input_underflows: AtomicCounter,
+    input_overflows: AtomicCounter,
+    output_underflows: AtomicCounter,
+    output_overflows: AtomicCounter,
+    frames: FrameCounts,
+}
+
+/// Accumulates the number of frames passed to each invocation of the callback.
+///
+/// Only the callback thread writes to these, so plain loads and stores suffice.
+#[derive(Default)]
+struct FrameCounts {
+    callbacks: AtomicCounter,
+    total: AtomicCounter,
+    min: AtomicCounter,
+    max: AtomicCounter,
+}
+
+/// The running totals of frames read from and written to a **Stream** since it was opened.
+///
+/// Shared with the callback of a **NonBlocking** stream and with the **StreamController** of a
+/// split one, so the totals may be read while the stream is in use elsewhere.
+#[derive(Debug, Default)]
+struct FrameTotals {
+    read: AtomicCounter,
+    written: AtomicCounter,
+}
+
+impl FrameTotals {
+    fn add_read(&self, frames: u64) {
+        self.read.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    fn add_written(&self, frames: u64) {
+        self.written.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    fn read(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+
+    fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+}
+
+/// Statistics on the number of frames passed to a **NonBlocking** stream's callback.
+///
+/// Useful for discovering the block sizes that a host API actually delivers when the stream was
+/// opened with `FRAMES_PER_BUFFER_UNSPECIFIED`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BufferStats {
+    /// The fewest frames passed to a single callback.
+    pub min_frames: u64,
+    /// The most frames passed to a single callback.
+    pub max_frames: u64,
+    /// The mean number of frames passed to each callback.
+    pub mean_frames: f64,
+    /// The number of callbacks over which the statistics were gathered.
+    pub callbacks: u64,
+}
+
+/// State that is updated by the callback and may be read by the owning **NonBlocking** stream.
+#[derive(Default)]
+struct CallbackState {
+    meters: Meters,
+    counters: Counters,
+    /// Set by **Stream::pause** and cleared by **Stream::resume**.
+    paused: AtomicBool,
+    /// Set by **Stream::request_stop**.
+    stop_requested: AtomicBool,
+    /// The length of the de-click fades in frames, or `0` if disabled. Set by
+    /// **Stream::set_declick**.
+    declick_frames: AtomicU32,
+    /// Set when the stream is started with de-click fades enabled.
+    fade_in_requested: AtomicBool,
+    /// Set when an active stream is about to be stopped.
+    fade_out_requested: AtomicBool,
+    /// Set by the callback once a requested fade out has completed, after which it outputs silence
+    /// until the stream is restarted.
+    faded_out: AtomicBool,
+    /// Set by **Stream::on_callback_panic**.
+    panic_handler: Mutex<Option<PanicHandler>>,
+    /// The payload of a panic caught by the callback that is yet to be reported.
+    panic_payload: Mutex<Option<Box<dyn std::any::Any + Send>>>,
+    /// The thread that reports caught panics, spawned when the first panic handler is registered.
+    panic_reporter: Mutex<Option<std::thread::Thread>>,
+    /// Set when the owning stream is dropped, so that the panic reporter thread exits.
+    closed: AtomicBool,
+}
+
+/// A handler registered via **Stream::on_callback_panic**.
+type PanicHandler = Box<dyn FnMut(&PanicPayload) + Send>;
+
+/// Describes a panic that was caught while running a **NonBlocking** stream's callback.
+///
+/// See **Stream::on_callback_panic**.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PanicPayload {
+    message: String,
+}
+
+impl PanicPayload {
+    /// Produce a **PanicPayload** from the payload returned by `std::panic::catch_unwind`.
+    fn from_any(payload: Box<dyn std::any::Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "Box<dyn Any>".to_string(),
+            },
+        };
+        PanicPayload { message: message }
+    }
+
+    /// The panic's message, or `"Box<dyn Any>"` if the panic was not raised with a string.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl CallbackState {
+    /// Called by **Stream::start** immediately before the stream is started.
+    fn starting(&self) {
+        let declick = self.declick_frames.load(Ordering::Relaxed) > 0;
+        // A stop requested while the stream was inactive must not end the new run immediately.
+        self.stop_requested.store(false, Ordering::Release);
+        self.fade_out_requested.store(false, Ordering::Release);
+        self.faded_out.store(false, Ordering::Release);
+        self.fade_in_requested.store(declick, Ordering::Release);
+    }
+
+    /// Called by **Stream::stop** before an active stream with the given sample rate is stopped.
+    ///
+    /// Waits for the callback to fade the output out so that stopping doesn't produce a click.
+    fn stopping(&self, sample_rate: f64) {
+        let frames = self.declick_frames.load(Ordering::Relaxed);
+        if frames == 0 {
+            return;
+        }
+        self.fade_out_requested.store(true, Ordering::Release);
+        // Allow for the callback buffers that are queued ahead of the fade.
+        let fade = std::time::Duration::from_secs_f64(f64::from(frames) / sample_rate);
+        let deadline = std::time::Instant::now() + fade + std::time::Duration::from_millis(250);
+        while !self.faded_out.load(Ordering::Acquire) && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    /// Called on the audio thread with the payload of a panic caught in the callback.
+    ///
+    /// Neither spawns a thread nor formats the payload, and never waits for a lock: the payload is
+    /// stashed and the reporter thread woken to pass it to the handler. If a previous panic is still
+    /// waiting to be reported, the new payload is leaked rather than freed on the audio thread.
+    fn stash_panic(&self, payload: Box<dyn std::any::Any + Send>) {
+        let mut payload = Some(payload);
+        if let Ok(mut slot) = self.panic_payload.try_lock() {
+            if slot.is_none() {
+                *slot = payload.take();
+            }
+        }
+        if let Some(payload) = payload {
+            std::mem::forget(payload);
+        }
+        if let Ok(reporter) = self.panic_reporter.try_lock() {
+            if let Some(ref reporter) = *reporter {
+                reporter.unpark();
+            }
+        }
+    }
+
+    /// Pass the stashed panic, if any, to the registered panic handler.
+    fn report_panic(&self) {
+        let payload = match self.panic_payload.lock() {
+            Ok(mut slot) => slot.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        let payload = match payload {
+            Some(payload) => PanicPayload::from_any(payload),
+            None => return,
+        };
+        let mut handler = match self.panic_handler.lock() {
+            Ok(handler) => handler,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(ref mut handler) = *handler {
+            handler(&payload);
+        }
+    }
+
+    /// Spawn the thread that reports caught panics, unless it is already running.
+    ///
+    /// The thread sleeps until woken by **CallbackState::stash_panic**, and exits once the owning
+    /// stream is dropped.
+    fn spawn_panic_reporter(state: &Arc<Self>) {
+        let mut reporter = match state.panic_reporter.lock() {
+            Ok(reporter) => reporter,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if reporter.is_some() {
+            return;
+        }
+        let thread_state = state.clone();
+        let builder = std::thread::Builder::new().name("portaudio-callback-panic".to_string());
+        let spawned = builder.spawn(move || loop {
+            // Report any panic caught before the thread was spawned or while it was reporting.
+            thread_state.report_panic();
+            if thread_state.closed.load(Ordering::Acquire) {
+                return;
+            }
+            std::thread::park();
+        });
+        // If no thread can be spawned the panic is still caught, but cannot be reported.
+        if let Ok(handle) = spawned {
+            *reporter = Some(handle.thread().clone());
+        }
+    }
+
+    /// Stop the panic reporter thread, if any.
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        let reporter = match self.panic_reporter.lock() {
+            Ok(reporter) => reporter,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(ref reporter) = *reporter {
+            reporter.unpark();
+        }
+    }
+}
+
+/// The number of buffer underflows and overflows reported to a **NonBlocking** stream's callback.
+///
+/// See the [**CallbackFlags**](./callback_flags/struct.CallbackFlags.html) for a description of
+/// each.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Xruns {
+    /// The number of callbacks flagged with **INPUT_UNDERFLOW**.
+    pub input_underflows: u64,
+    /// The number of callbacks flagged with **INPUT_OVERFLOW**.
+    pub input_overflows: u64,
+    /// The number of callbacks flagged with **OUTPUT_UNDERFLOW**.
+    pub output_underflows: u64,
+    /// The number of callbacks flagged with **OUTPUT_OVERFLOW**.
+    pub output_overflows: u64,
+}
+
+impl Xruns {
+    /// The total number of underflows and overflows in either direction.
+    pub fn total(&self) -> u64 {
+        self.input_underflows
+            + self.input_overflows
+            + self.output_underflows
+            + self.output_overflows
+    }
+}
+
+impl Counters {
+    /// Count a callback invocation along with any xruns indicated by its `flags`.
+    fn record(&self, flags: CallbackFlags) {
+        let count = |counter: &AtomicCounter, flag: CallbackFlags| {
+            if flags.contains(flag) {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        count(&self.input_underflows, callback_flags::INPUT_UNDERFLOW);
+        count(&self.input_overflows, callback_flags::INPUT_OVERFLOW);
+        count(&self.output_underflows, callback_flags::OUTPUT_UNDERFLOW);
+        count(&self.output_overflows, callback_flags::OUTPUT_OVERFLOW);
+    }
+
+    fn xruns(&self) -> Xruns {
+        Xruns {
+            input_underflows: self.input_underflows.load(Ordering::Relaxed),
+            input_overflows: self.input_overflows.load(Ordering::Relaxed),
+            output_underflows: self.output_underflows.load(Ordering::Relaxed),
+            output_overflows: self.output_overflows.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl FrameCounts {
+    /// Account for a callback that was passed the given number of `frames`.
+    fn record(&self, frames: u64) {
+        let callbacks = self.callbacks.load(Ordering::Relaxed);
+        if callbacks == 0 || frames < self.min.load(Ordering::Relaxed) {
+            self.min.store(frames, Ordering::Relaxed);
+        }
+        if frames > self.max.load(Ordering::Relaxed) {
+            self.max.store(frames, Ordering::Relaxed);
+        }
+        self.total.fetch_add(frames, Ordering::Relaxed);
+        self.callbacks.store(callbacks + 1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> BufferStats {
+        let callbacks = self.callbacks.load(Ordering::Relaxed);
+        if callbacks == 0 {
+            return BufferStats::default();
+        }
+        let total = self.total.load(Ordering::Relaxed);
+        BufferStats {
+            min_frames: self.min.load(Ordering::Relaxed),
+            max_frames: self.max.load(Ordering::Relaxed),
+            mean_frames: total as f64 / callbacks as f64,
+            callbacks: callbacks,
+        }
+    }
+}
+
+/// A summary of the state of a **NonBlocking** stream at a single point in time.
+///
+/// Produced by **Stream::snapshot**. All fields are plain values, so a **Snapshot** may be freely
+/// sent to and read from other threads (e.g. to serve a diagnostics endpoint). With the `serde`
+/// feature enabled it may also be serialized.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Snapshot {
+    /// Whether or not the stream is currently active. See **Stream::is_active**.
+    pub is_active: bool,
+    /// Whether or not the stream is currently stopped. See **Stream::is_stopped**.
+    pub is_stopped: bool,
+    /// The fraction of CPU time consumed by the stream's callback. See **Stream::cpu_load**.
+    pub cpu_load: f64,
+    /// The current stream time in seconds. See **Stream::time**.
+    pub time: Time,
+    /// The latencies and sample rate of the stream. See **Stream::info**.
+    pub info: Info,
+    /// The number of times the callback has been called. See **Stream::callback_count**.
+    pub callback_count: u64,
+    /// The xruns reported to the callback. See **Stream::xruns**.
+    pub xruns: Xruns,
+    /// The number of frames passed to the callback. See **Stream::buffer_stats**.
+    pub buffer_stats: BufferStats,
+}
+
+impl LevelMeter {
+    /// Enable or disable the meter, resetting the stored level to silence.
+    ///
+    /// A block that was being measured as the meter was disabled may still store its level
+    /// afterwards, which is why **LevelMeter::load** ignores the stored level while disabled.
+    fn set_enabled(&self, enabled: bool) {
+        if enabled {
+            self.store(Level::default());
+            self.enabled.store(true, Ordering::Release);
+        } else {
+            self.enabled.store(false, Ordering::Release);
+            self.store(Level::default());
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    fn store(&self, level: Level) {
+        self.rms.store(level.rms.to_bits(), Ordering::Relaxed);
+        self.peak.store(level.peak.to_bits(), Ordering::Relaxed);
+    }
+
+    /// The most recently measured **Level**, or silence if the meter is disabled.
+    fn load(&self) -> Level {
+        if !self.is_enabled() {
+            return Level::default();
+        }
+        Level {
+            rms: f32::from_bits(self.rms.load(Ordering::Relaxed)),
+            peak: f32::from_bits(self.peak.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Measure the given callback buffer and store its **Level** if the meter is enabled.
+    fn measure(&self, buffer: *const raw::c_void, format: SampleFormatFlags, len: usize) {
+        if buffer.is_null() || !self.is_enabled() {
+            return;
+        }
+        if let Some(level) = unsafe { block_level(buffer, format, len) } {
+            self.store(level);
+        }
+    }
+}
+
+/// Calculate the **Level** of `len` interleaved samples of the given format.
+///
+/// Returns `None` for non-interleaved, custom or unknown formats.
+///
+/// This performs no allocation so that it may be called from the audio thread.
+unsafe fn block_level(
+    buffer: *const raw::c_void,
+    format: SampleFormatFlags,
+    len: usize,
+) -> Option<Level> {
+    fn level<T, N>(buffer: *const raw::c_void, len: usize, normalise: N) -> Level
+    where
+        T: Copy,
+        N: Fn(T) -> f32,
+    {
+        let samples = unsafe { std::slice::from_raw_parts(buffer as *const T, len) };
+        let (mut sum_sq, mut peak) = (0.0, 0.0f32);
+        for &sample in samples {
+            let sample = normalise(sample);
+            sum_sq += sample * sample;
+            peak = peak.max(sample.abs());
+        }
+        let rms = if len == 0 {
+            0.0
+        } else {
+            (sum_sq / len as f32).sqrt()
+        };
+        Level {
+            rms: rms,
+            peak: peak,
+        }
+    }
+
+    if format.contains(sample_format_flags::NON_INTERLEAVED) {
+        return None;
+    }
+    let level = match SampleFormat::from_flags(format) {
+        SampleFormat::F32 => level(buffer, len, |s: f32| s),
+        SampleFormat::I32 => level(buffer, len, |s: i32| s as f32 / 2_147_483_648.0),
+        SampleFormat::I24 => level(buffer, len, |s: [u8; 3]| read_i24(s) as f32 / 8_388_608.0),
+        SampleFormat::I16 => level(buffer, len, |s: i16| f32::from(s) / 32_768.0),
+        SampleFormat::I8 => level(buffer, len, |s: i8| f32::from(s) / 128.0),
+        SampleFormat::U8 => level(buffer, len, |s: u8| (f32::from(s) - 128.0) / 128.0),
+        SampleFormat::Custom | SampleFormat::Unknown => return None,
+    };
+    Some(level)
+}
+
+/// Decode a packed little-endian 24-bit sample.
+fn read_i24(bytes: [u8; 3]) -> i32 {
+    i32::from(bytes[0]) | i32::from(bytes[1]) << 8 | i32::from(bytes[2] as i8) << 16
+}
+
+/// Call `f` with each contiguous buffer within the given callback buffer, along with the number of
+/// channels interleaved within it.
+unsafe fn for_each_buffer<F>(
+    buffer: *mut raw::c_void,
+    format: SampleFormatFlags,
+    channels: usize,
+    mut f: F,
+) where
+    F: FnMut(*mut raw::c_void, usize),
+{
+    if format.contains(sample_format_flags::NON_INTERLEAVED) {
+        let buffers = buffer as *const *mut raw::c_void;
+        for channel in 0..channels {
+            f(*buffers.add(channel), 1);
+        }
+    } else {
+        f(buffer, channels);
+    }
+}
+
+/// Fill `frames` frames of the given callback buffer with silence.
+///
+/// Custom and unknown formats are left untouched.
+unsafe fn write_silence(
+    buffer: *mut raw::c_void,
+    format: SampleFormatFlags,
+    frames: usize,
+    channels: usize,
+) {
+    let sample_format = SampleFormat::from_flags(format);
+    let silence = silence_byte(sample_format);
+    let frame_bytes = frames * sample_format.size_in_bytes() as usize;
+    for_each_buffer(buffer, format, channels, |buffer, channels| {
+        ptr::write_bytes(buffer as *mut u8, silence, frame_bytes * channels);
+    });
+}
+
+/// Fill `frames` frames of a single `channel` of the given callback buffer with silence.
+///
+/// Custom and unknown formats are left untouched.
+unsafe fn write_channel_silence(
+    buffer: *mut raw::c_void,
+    format: SampleFormatFlags,
+    frames: usize,
+    channels: usize,
+    channel: usize,
+) {
+    let sample_format = SampleFormat::from_flags(format);
+    let silence = silence_byte(sample_format);
+    let sample_bytes = sample_format.size_in_bytes() as usize;
+    if format.contains(sample_format_flags::NON_INTERLEAVED) {
+        let buffer = *(buffer as *const *mut u8).add(channel);
+        ptr::write_bytes(buffer, silence, frames * sample_bytes);
+    } else {
+        let buffer = buffer as *mut u8;
+        for frame in 0..frames {
+            let sample = buffer.add((frame * channels + channel) * sample_bytes);
+            ptr::write_bytes(sample, silence, sample_bytes);
+        }
+    }
+}
+
+/// The byte with which buffers of the given format are filled to produce silence.
+fn silence_byte(sample_format: SampleFormat) -> u8 {
+    if sample_format == SampleFormat::U8 {
+        0x80
+    } else {
+        0
+    }
+}
+
+/// Scale `frames` frames of the given callback buffer by a gain that ramps linearly from `from`
+/// to `to` over the course of the buffer.
+///
+/// Custom and unknown formats are left untouched.
+unsafe fn apply_ramp(
+    buffer: *mut raw::c_void,
+    format: SampleFormatFlags,
+    frames: usize,
+    channels: usize,
+    from: f32,
+    to: f32,
+) {
+    apply_fade(buffer, format, frames, channels, from, to, frames)
+}
+
+/// Scale `frames` frames of the given callback buffer by a gain that ramps linearly from `from`
+/// to `to` over the first `ramp_frames` frames and remains at `to` for the rest of the buffer.
+///
+/// Custom and unknown formats are left untouched.
+unsafe fn apply_fade(
+    buffer: *mut raw::c_void,
+    format: SampleFormatFlags,
+    frames: usize,
+    channels: usize,
+    from: f32,
+    to: f32,
+    ramp_frames: usize,
+) {
+    fn ramp<T, S>(
+        buffer: *mut raw::c_void,
+        frames: usize,
+        channels: usize,
+        (from, to, ramp_frames): (f32, f32, usize),
+        scale: S,
+    ) where
+        S: Fn(&mut T, f32),
+    {
+        if channels == 0 {
+            return;
+        }
+        let samples =
+            unsafe { std::slice::from_raw_parts_mut(buffer as *mut T, frames * channels) };
+        for (frame, samples) in samples.chunks_mut(channels).enumerate() {
+            let gain = if frame < ramp_frames {
+                from + (to - from) * frame as f32 / ramp_frames as f32
+            } else {
+                to
+            };
+            for sample in samples {
+                scale(sample, gain);
+            }
+        }
+    }
+
+    fn scale<S: Sample>(sample: &mut S, gain: f32) {
+        *sample = S::from_normalised_f32(sample.to_normalised_f32() * gain);
+    }
+
+    fn scale_i24(sample: &mut [u8; 3], gain: f32) {
+        let scaled = (read_i24(*sample) as f32 * gain) as i32;
+        sample.copy_from_slice(&scaled.to_le_bytes()[..3]);
+    }
+
+    let sample_format = SampleFormat::from_flags(format);
+    let gain = (from, to, ramp_frames);
+    for_each_buffer(
+        buffer,
+        format,
+        channels,
+        |buffer, channels| match sample_format {
+            SampleFormat::F32 => ramp(buffer, frames, channels, gain, scale::<f32>),
+            SampleFormat::I32 => ramp(buffer, frames, channels, gain, scale::<i32>),
+            SampleFormat::I24 => ramp(buffer, frames, channels, gain, scale_i24),
+            SampleFormat::I16 => ramp(buffer, frames, channels, gain, scale::<i16>),
+            SampleFormat::I8 => ramp(buffer, frames, channels, gain, scale::<i8>),
+            SampleFormat::U8 => ramp(buffer, frames, channels, gain, scale::<u8>),
+            SampleFormat::Custom | SampleFormat::Unknown => (),
+        },
+    );
+}
+
+impl Fade {
+    fn new(from: f32, to: f32, frames: u32) -> Self {
+        Fade {
+            from: from,
+            to: to,
+            position: 0,
+            frames: frames,
+        }
+    }
+
+    /// The gain after `position` frames of the fade.
+    fn gain_at(&self, position: u32) -> f32 {
+        self.from + (self.to - self.from) * position as f32 / self.frames as f32
+    }
+
+    /// Apply the next `frames` frames of the fade to the given callback buffer, holding the final
+    /// gain for any frames beyond the end of the fade.
+    ///
+    /// Returns `true` once the fade has completed.
+    unsafe fn apply(
+        &mut self,
+        buffer: *mut raw::c_void,
+        format: SampleFormatFlags,
+        frames: usize,
+        channels: usize,
+    ) -> bool {
+        let ramp_frames = std::cmp::min(frames, (self.frames - self.position) as usize);
+        let end = self.position + ramp_frames as u32;
+        let (from, to) = (self.gain_at(self.position), self.gain_at(end));
+        apply_fade(buffer, format, frames, channels, from, to, ramp_frames);
+        self.position = end;
+        self.position >= self.frames
+    }
+}
+
+/// Apply the de-click fades requested via the callback's state to the output buffer.
+unsafe fn declick(callback: &mut CallbackFnWrapper, output: *mut raw::c_void, frames: usize) {
+    let state = &callback.state;
+    let (format, channels) = (callback.out_format, callback.out_channels as usize);
+    let fade_frames = state.declick_frames.load(Ordering::Relaxed);
+    if state.fade_in_requested.swap(false, Ordering::AcqRel) && fade_frames > 0 {
+        callback.fade = Some(Fade::new(0.0, 1.0, fade_frames));
+    }
+    if state.fade_out_requested.swap(false, Ordering::AcqRel) {
+        // Fade out from wherever a fade in has reached.
+        let from = callback
+            .fade
+            .map_or(1.0, |fade| fade.gain_at(fade.position));
+        callback.fade = Some(Fade::new(from, 0.0, std::cmp::max(fade_frames, 1)));
+    }
+    if state.faded_out.load(Ordering::Acquire) {
+        write_silence(output, format, frames, channels);
+        return;
+    }
+    if let Some(mut fade) = callback.fade.take() {
+        if !fade.apply(output, format, frames, channels) {
+            callback.fade = Some(fade);
+        } else if fade.to == 0.0 {
+            state.faded_out.store(true, Ordering::Release);
+        }
+    }
 }
 
 /// Timing information for the buffer passed to the input stream callback.
@@ -136,6 +866,34 @@ pub struct DuplexCallbackTimeInfo {
     pub out_buffer_dac: Time,
 }
 
+impl InputCallbackTimeInfo {
+    /// The time at which the frame at the given index within the input buffer was captured at the
+    /// ADC input.
+    ///
+    /// This is calculated as `buffer_adc + frame / sample_rate`, where `sample_rate` should be the
+    /// sample rate of the stream (see `Stream::info`). This is useful for correlating captured audio
+    /// with external events, e.g. video frames.
+    pub fn frame_adc(&self, frame: usize, sample_rate: f64) -> Time {
+        frame_time(self.buffer_adc, frame, sample_rate)
+    }
+}
+
+impl DuplexCallbackTimeInfo {
+    /// The time at which the frame at the given index within the input buffer was captured at the
+    /// ADC input.
+    ///
+    /// This is calculated as `in_buffer_adc + frame / sample_rate`, where `sample_rate` should be
+    /// the sample rate of the stream (see `Stream::info`).
+    pub fn in_frame_adc(&self, frame: usize, sample_rate: f64) -> Time {
+        frame_time(self.in_buffer_adc, frame, sample_rate)
+    }
+}
+
+/// The time of the frame at the given index within a buffer whose first frame occurs at `start`.
+fn frame_time(start: Time, frame: usize, sample_rate: f64) -> Time {
+    start + frame as Time / sample_rate
+}
+
 /// Arguments given to a **NonBlocking** **Input** **Stream**'s **CallbackFn**.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct InputCallbackArgs<'a, I: 'a> {
@@ -150,7 +908,7 @@ pub struct InputCallbackArgs<'a, I: 'a> {
     pub time: InputCallbackTimeInfo,
 }
 
-/// Arguments given to a **NonBlocking** **Input** **Stream**'s **CallbackFn**.
+/// Arguments given to a **NonBlocking** **Output** **Stream**'s **CallbackFn**.
 #[derive(Debug, PartialEq)]
 pub struct OutputCallbackArgs<'a, O: 'a> {
     /// The **Output** **Stream**'s buffer, to which we will write our interleaved audio data.
@@ -164,7 +922,11 @@ pub struct OutputCallbackArgs<'a, O: 'a> {
     pub time: OutputCallbackTimeInfo,
 }
 
-/// Arguments given to a **NonBlocking** **Input** **Stream**'s **CallbackFn**.
+/// Arguments given to a **NonBlocking** **Duplex** **Stream**'s **CallbackFn**.
+///
+/// The input and output buffers have their own sample types, **I** and **O** respectively, which
+/// need not be the same. Each buffer contains `frames` multiplied by the channel count of its
+/// direction.
 #[derive(Debug, PartialEq)]
 pub struct DuplexCallbackArgs<'a, I: 'a, O: 'a> {
     /// The buffer of interleaved samples read from the **Stream**'s ADC.
@@ -186,11 +948,21 @@ pub struct DuplexCallbackArgs<'a, I: 'a, O: 'a> {
 /// buffer for the **Blocking** stream.
 pub struct Blocking<B> {
     buffer: B,
+    /// The number of frames that fit within each of the `buffer`s.
+    frames_per_buffer: u32,
+    /// The number of reads for which PortAudio reported that input was discarded.
+    input_overflows: ::std::cell::Cell<u64>,
+    /// Wakes tasks waiting within **Stream::poll_write**, spawned when first needed.
+    #[cfg(feature = "async")]
+    write_waker: Option<WakeTimer>,
 }
 
 /// A **Stream** **Mode** representing a non-blocking stream.
 pub struct NonBlocking {
     callback: Box<CallbackFnWrapper>,
+    state: Arc<CallbackState>,
+    /// Shared with the callback and replaced whenever the output channel count changes.
+    output_mutes: Arc<Vec<AtomicBool>>,
 }
 
 /// A type-safe PortAudio PaStream wrapper.
@@ -226,6 +998,13 @@ pub struct NonBlocking {
 /// A [**NonBlocking**][7] **Stream** can be opened using the
 /// [**PortAudio::open_non_blocking_stream][13]** method.
 ///
+/// The methods of each **Mode** are implemented for that mode alone. A [**NonBlocking**][7]
+/// stream has no `read` or `write` methods, which PortAudio would reject with
+/// `Error::CanNotReadFromACallbackStream` or `Error::CanNotWriteToACallbackStream`. Likewise, a
+/// [**Blocking**][6] stream has no callback, so it lacks the methods that control one, such as
+/// **Stream::pause** and **Stream::request_stop**. Mixing up the two is therefore a compile error
+/// rather than a runtime one.
+///
 /// A **Stream** may only live as long as the **PortAudio** instance from which it was spawned and
 /// no longer.
 ///
@@ -254,6 +1033,29 @@ pub struct Stream<M, F> {
     mode: M,
     flow: F,
     port_audio_life: std::sync::Arc<super::Life>,
+    /// Whether the stream counts towards `PortAudio::set_max_concurrent_streams`.
+    holds_slot: bool,
+    /// The settings with which the stream was opened, retained for reopening it in another mode.
+    settings: OpenSettings,
+    /// The frames read from and written to the stream since it was opened.
+    frame_totals: Arc<FrameTotals>,
+    /// The state shared with the callback of a **NonBlocking** stream, through which starting and
+    /// stopping fade the output.
+    callback_state: Option<Arc<CallbackState>>,
+}
+
+/// The settings other than the **Flow** with which a **Stream** was opened.
+#[derive(Copy, Clone)]
+struct OpenSettings {
+    sample_rate: f64,
+    frames_per_buffer: u32,
+    flags: Flags,
+}
+
+/// **Settings** for reopening a **Stream** with the **Flow** and settings of a closed one.
+struct Reopen<F> {
+    flow: F,
+    settings: OpenSettings,
 }
 
 /// Parameters for one direction (input or output) of a stream.
@@ -274,8 +1076,19 @@ pub struct Parameters<S> {
     pub is_interleaved: bool,
     /// Sample format of the audio data provided to/by the device.
     sample_format: std::marker::PhantomData<S>,
+    /// An optional host API specific stream info structure.
+    host_api_specific_stream_info: HostApiSpecificStreamInfo,
 }
 
+/// A pointer to a host API specific stream info structure (or null), passed to PortAudio via the
+/// `hostApiSpecificStreamInfo` field of the C stream parameters.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct HostApiSpecificStreamInfo(*mut raw::c_void);
+
+// The pointee is only read by PortAudio while opening a stream or checking format support.
+unsafe impl Send for HostApiSpecificStreamInfo {}
+unsafe impl Sync for HostApiSpecificStreamInfo {}
+
 /// Settings used to construct an **Input** **Stream**.
 #[derive(Copy, Clone, Debug)]
 pub struct InputSettings<I> {
@@ -303,6 +1116,15 @@ pub struct OutputSettings<O> {
 }
 
 /// Settings used to construct a **Duplex** **Stream**.
+///
+/// The input and output may use different sample formats (e.g. capturing `i16` while playing back
+/// `f32`) by using different types for **I** and **O**. Each direction's buffers are sized using
+/// its own sample type and channel count.
+///
+/// Either side may be given a `channel_count` of `0`, in which case that direction is omitted when
+/// the stream is opened, producing an input-only or output-only stream whose callback receives an
+/// empty buffer for the missing side. Opening a stream without channels in either direction fails
+/// with `Err(Error::InvalidChannelCount)`.
 #[derive(Copy, Clone, Debug)]
 pub struct DuplexSettings<I, O> {
     /// The set of Parameters necessary for constructing the input **Stream**.
@@ -318,22 +1140,42 @@ pub struct DuplexSettings<I, O> {
 }
 
 /// A type of **Flow** that describes an input-only **Stream**.
+#[derive(Clone)]
 pub struct Input<I> {
     params: Parameters<I>,
 }
 
 /// A type of **Flow** that describes an output-only **Stream**.
+#[derive(Clone)]
 pub struct Output<O> {
     params: Parameters<O>,
 }
 
 /// A type of **Flow** that describes a bi-directional (input *and* output) **Stream**.
+#[derive(Clone)]
 pub struct Duplex<I, O> {
     in_params: Parameters<I>,
     out_params: Parameters<O>,
 }
 
+/// A type of **Flow** that describes a **Stream** opened from raw `PaStreamParameters`.
+///
+/// See **PortAudio::open_blocking_stream_raw**.
+pub struct Raw {
+    in_params: Option<ffi::PaStreamParameters>,
+    out_params: Option<ffi::PaStreamParameters>,
+}
+
 unsafe impl Send for NonBlocking {}
+
+// The stream is closed before its mode is dropped, so the callback can no longer panic.
+impl Drop for NonBlocking {
+    fn drop(&mut self) {
+        self.state.close();
+    }
+}
+// Each **Buffer** exclusively owns its allocation.
+unsafe impl Send for Buffer {}
 unsafe impl<M, F> Send for Stream<M, F>
 where
     M: Send,
@@ -382,6 +1224,41 @@ impl<S> Parameters<S> {
             is_interleaved: is_interleaved,
             suggested_latency: suggested_latency,
             sample_format: std::marker::PhantomData,
+            host_api_specific_stream_info: HostApiSpecificStreamInfo(ptr::null_mut()),
+        }
+    }
+
+    /// Attach a host API specific stream info structure to the parameters.
+    ///
+    /// The structure is given to PortAudio as the `hostApiSpecificStreamInfo` and must belong to
+    /// the host API of the `device`. See the [**ext**](../ext/index.html) module for types that
+    /// construct these structures.
+    ///
+    /// # Safety
+    ///
+    /// `info` must either be null or point to a valid host API specific stream info structure that
+    /// remains valid for as long as these **Parameters** are used to open a **Stream** or to check
+    /// whether a format is supported.
+    pub unsafe fn with_host_api_specific_stream_info(mut self, info: *mut raw::c_void) -> Self {
+        self.host_api_specific_stream_info = HostApiSpecificStreamInfo(info);
+        self
+    }
+
+    /// The same **Parameters** without any host API specific stream info.
+    fn without_host_api_specific_stream_info(mut self) -> Self {
+        self.host_api_specific_stream_info = HostApiSpecificStreamInfo(ptr::null_mut());
+        self
+    }
+
+    /// The same **Parameters** for a different sample type.
+    pub fn with_sample_type<T>(self) -> Parameters<T> {
+        Parameters {
+            device: self.device,
+            channel_count: self.channel_count,
+            is_interleaved: self.is_interleaved,
+            suggested_latency: self.suggested_latency,
+            sample_format: std::marker::PhantomData,
+            host_api_specific_stream_info: self.host_api_specific_stream_info,
         }
     }
 }
@@ -463,7 +1340,7 @@ where
     type CallbackArgs = InputCallbackArgs<'static, I>;
     type CallbackTimeInfo = InputCallbackTimeInfo;
 
-    fn new_buffer(&self, frames_per_buffer: u32) -> Self::Buffer {
+    fn new_buffer(&self, frames_per_buffer: u32) -> Result<Self::Buffer, Error> {
         let channel_count = self.params.channel_count;
         Buffer::new::<I>(frames_per_buffer, channel_count)
     }
@@ -474,7 +1351,13 @@ where
         Option<ffi::PaStreamParameters>,
         Option<ffi::PaStreamParameters>,
     ) {
-        (Some(self.params.into()), None)
+        (direction_params(self.params), None)
+    }
+
+    fn without_host_api_specific_stream_info(self) -> Self {
+        Input {
+            params: self.params.without_host_api_specific_stream_info(),
+        }
     }
 
     fn new_callback_args(
@@ -524,10 +1407,16 @@ where
         Option<ffi::PaStreamParameters>,
         Option<ffi::PaStreamParameters>,
     ) {
-        (None, Some(self.params.into()))
+        (None, direction_params(self.params))
+    }
+
+    fn without_host_api_specific_stream_info(self) -> Self {
+        Output {
+            params: self.params.without_host_api_specific_stream_info(),
+        }
     }
 
-    fn new_buffer(&self, frames_per_buffer: u32) -> Self::Buffer {
+    fn new_buffer(&self, frames_per_buffer: u32) -> Result<Self::Buffer, Error> {
         let channel_count = self.params.channel_count;
         Buffer::new::<O>(frames_per_buffer, channel_count)
     }
@@ -580,15 +1469,25 @@ where
         Option<ffi::PaStreamParameters>,
         Option<ffi::PaStreamParameters>,
     ) {
-        (Some(self.in_params.into()), Some(self.out_params.into()))
+        (
+            direction_params(self.in_params),
+            direction_params(self.out_params),
+        )
+    }
+
+    fn without_host_api_specific_stream_info(self) -> Self {
+        Duplex {
+            in_params: self.in_params.without_host_api_specific_stream_info(),
+            out_params: self.out_params.without_host_api_specific_stream_info(),
+        }
     }
 
-    fn new_buffer(&self, frames_per_buffer: u32) -> Self::Buffer {
+    fn new_buffer(&self, frames_per_buffer: u32) -> Result<Self::Buffer, Error> {
         let in_channel_count = self.in_params.channel_count;
-        let in_buffer = Buffer::new::<I>(frames_per_buffer, in_channel_count);
+        let in_buffer = Buffer::new::<I>(frames_per_buffer, in_channel_count)?;
         let out_channel_count = self.out_params.channel_count;
-        let out_buffer = Buffer::new::<O>(frames_per_buffer, out_channel_count);
-        (in_buffer, out_buffer)
+        let out_buffer = Buffer::new::<O>(frames_per_buffer, out_channel_count)?;
+        Ok((in_buffer, out_buffer))
     }
 
     fn new_callback_args(
@@ -611,12 +1510,17 @@ where
         // TODO: At the moment, we assume these buffers are interleaved. We need to check whether
         // or not buffer is interleaved here. This should probably an extra type parameter
         // (along-side the Sample type param).
-        let in_buffer: &[I] = {
+        // Either side may have no channels, in which case PortAudio passes a null buffer.
+        let in_buffer: &[I] = if input.is_null() {
+            &[]
+        } else {
             let buffer_len = in_channels as usize * frame_count as usize;
             let buffer_ptr = input as *const I;
             unsafe { std::slice::from_raw_parts(buffer_ptr, buffer_len) }
         };
-        let out_buffer: &mut [O] = {
+        let out_buffer: &mut [O] = if output.is_null() {
+            &mut []
+        } else {
             let buffer_len = out_channels as usize * frame_count as usize;
             let buffer_ptr = output as *mut O;
             unsafe { std::slice::from_raw_parts_mut(buffer_ptr, buffer_len) }
@@ -631,13 +1535,55 @@ where
     }
 }
 
-impl<I> Reader for Input<I>
-where
-    I: Sample + 'static,
-{
-    type Sample = I;
-    fn readable_buffer(blocking: &Blocking<<Input<I> as Flow>::Buffer>) -> &Buffer {
-        &blocking.buffer
+impl Flow for Raw {
+    type Buffer = ();
+    type CallbackArgs = ();
+    type CallbackTimeInfo = ();
+
+    fn new_buffer(&self, _frames_per_buffer: u32) -> Result<Self::Buffer, Error> {
+        Ok(())
+    }
+
+    fn params_both_directions(
+        &self,
+    ) -> (
+        Option<ffi::PaStreamParameters>,
+        Option<ffi::PaStreamParameters>,
+    ) {
+        (self.in_params, self.out_params)
+    }
+
+    fn without_host_api_specific_stream_info(self) -> Self {
+        let clear = |mut params: ffi::PaStreamParameters| {
+            params.hostApiSpecificStreamInfo = ptr::null_mut();
+            params
+        };
+        Raw {
+            in_params: self.in_params.map(clear),
+            out_params: self.out_params.map(clear),
+        }
+    }
+
+    // Raw streams may only be opened in **Blocking** mode, so there are no callback arguments.
+    fn new_callback_args(
+        _input: *const raw::c_void,
+        _output: *mut raw::c_void,
+        _frame_count: raw::c_ulong,
+        _time_info: *const ffi::PaStreamCallbackTimeInfo,
+        _flags: ffi::PaStreamCallbackFlags,
+        _in_channels: i32,
+        _out_channels: i32,
+    ) -> Self::CallbackArgs {
+    }
+}
+
+impl<I> Reader for Input<I>
+where
+    I: Sample + 'static,
+{
+    type Sample = I;
+    fn readable_buffer(blocking: &Blocking<<Input<I> as Flow>::Buffer>) -> &Buffer {
+        &blocking.buffer
     }
     fn channel_count(&self) -> i32 {
         self.params.channel_count
@@ -814,7 +1760,20 @@ pub struct CallbackTimeInfo {
 }
 
 /// A structure containing unchanging information about an open stream.
-#[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
+///
+/// PortAudio may append fields to its `PaStreamInfo` in later versions of the struct, so each
+/// field is only read from PortAudio if the library's `structVersion` includes it, and is left as
+/// its default otherwise. The fields and the versions that introduced them are:
+///
+/// | Field | `structVersion` |
+/// |-------|-----------------|
+/// | `input_latency` | 1 |
+/// | `output_latency` | 1 |
+/// | `sample_rate` | 1 |
+///
+/// Version 1 is the only version released so far, used by every PortAudio v19 release.
+#[derive(Copy, Clone, Default, PartialEq, PartialOrd, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[repr(C)]
 pub struct Info {
     /// Struct version
@@ -827,6 +1786,37 @@ pub struct Info {
     pub sample_rate: f64,
 }
 
+impl Info {
+    /// The `structVersion` of the `PaStreamInfo` from which this **Info** was read, or `0` if
+    /// PortAudio returned no info (e.g. because the stream is closed).
+    ///
+    /// See the [**Info**](./struct.Info.html) docs for the fields introduced by each version.
+    pub fn struct_version(&self) -> i32 {
+        self.struct_version
+    }
+
+    /// Read the fields of the given `PaStreamInfo` that are present in its `structVersion`.
+    ///
+    /// The struct is read field by field rather than copied as a whole, so that it is never read
+    /// beyond the end of an older, shorter version. Returns the default **Info** if `info` is null.
+    unsafe fn from_ptr(info: *const ffi::PaStreamInfo) -> Info {
+        if info.is_null() {
+            return Info::default();
+        }
+        let struct_version = (*info).structVersion;
+        let mut result = Info {
+            struct_version: struct_version,
+            ..Info::default()
+        };
+        if struct_version >= 1 {
+            result.input_latency = (*info).inputLatency;
+            result.output_latency = (*info).outputLatency;
+            result.sample_rate = (*info).sampleRate;
+        }
+        result
+    }
+}
+
 impl From<ffi::PaStreamInfo> for Info {
     fn from(info: ffi::PaStreamInfo) -> Info {
         Info {
@@ -839,6 +1829,7 @@ impl From<ffi::PaStreamInfo> for Info {
 }
 
 impl<B> Mode for Blocking<B> {}
+
 impl Mode for NonBlocking {}
 
 impl<S: Sample> Parameters<S> {
@@ -866,10 +1857,30 @@ impl<S: Sample> Parameters<S> {
             suggested_latency: c_params.suggestedLatency,
             is_interleaved: is_interleaved,
             sample_format: std::marker::PhantomData,
+            host_api_specific_stream_info: HostApiSpecificStreamInfo(
+                c_params.hostApiSpecificStreamInfo,
+            ),
         })
     }
 }
 
+impl<S: Sample> Parameters<S> {
+    /// The number of samples within a single frame, i.e. the channel count.
+    pub fn samples_per_frame(&self) -> usize {
+        self.channel_count as usize
+    }
+
+    /// The number of bytes occupied by a single frame, i.e. the channel count multiplied by the
+    /// size of the sample format.
+    ///
+    /// The size of the sample format is that of the format as transferred by PortAudio, so packed
+    /// 24-bit samples occupy 3 bytes each. Use this rather than multiplying by hand when sizing
+    /// buffers for byte-level I/O.
+    pub fn bytes_per_frame(&self) -> usize {
+        self.samples_per_frame() * S::sample_format().size_in_bytes() as usize
+    }
+}
+
 impl<S: Sample> From<Parameters<S>> for ffi::PaStreamParameters {
     /// Converts the **Parameters** into its matching `C_PaStreamParameters`.
     fn from(params: Parameters<S>) -> Self {
@@ -878,6 +1889,7 @@ impl<S: Sample> From<Parameters<S>> for ffi::PaStreamParameters {
             channel_count,
             suggested_latency,
             is_interleaved,
+            host_api_specific_stream_info,
             ..
         } = params;
         let sample_format = S::sample_format();
@@ -890,11 +1902,24 @@ impl<S: Sample> From<Parameters<S>> for ffi::PaStreamParameters {
             channelCount: channel_count as raw::c_int,
             sampleFormat: sample_format_flags.bits(),
             suggestedLatency: suggested_latency,
-            hostApiSpecificStreamInfo: ptr::null_mut(),
+            hostApiSpecificStreamInfo: host_api_specific_stream_info.0,
         }
     }
 }
 
+impl<F> Settings for Reopen<F> {
+    type Flow = F;
+    fn into_flow_and_settings(self) -> (Self::Flow, f64, u32, Flags) {
+        let Reopen { flow, settings } = self;
+        (
+            flow,
+            settings.sample_rate,
+            settings.frames_per_buffer,
+            settings.flags,
+        )
+    }
+}
+
 impl<I> Settings for InputSettings<I> {
     type Flow = Input<I>;
     fn into_flow_and_settings(self) -> (Self::Flow, f64, u32, Flags) {
@@ -941,21 +1966,42 @@ impl<I, O> Settings for DuplexSettings<I, O> {
     }
 }
 
+/// The number of interleaved samples within the given number of frames.
+///
+/// Returns `Err(Error::InvalidChannelCount)` if `channels` is negative.
+///
+/// Returns `Err(Error::BufferTooBig)` if the number of samples would overflow `usize`.
+fn samples_len(frames: u32, channels: i32) -> Result<usize, Error> {
+    if channels < 0 {
+        return Err(Error::InvalidChannelCount);
+    }
+    (frames as usize)
+        .checked_mul(channels as usize)
+        .ok_or(Error::BufferTooBig)
+}
+
 impl Buffer {
     /// Construct a new **Buffer** for transferring audio on a stream with the given format.
-    fn new<S>(frames_per_buffer: u32, channel_count: i32) -> Buffer {
+    ///
+    /// Returns `Err(Error::BufferTooBig)` if the size of the buffer would overflow `usize`.
+    fn new<S>(frames_per_buffer: u32, channel_count: i32) -> Result<Buffer, Error> {
         let sample_format_bytes = ::std::mem::size_of::<S>() as libc::size_t;
-        let n_frames = frames_per_buffer as libc::size_t;
-        let n_channels = channel_count as libc::size_t;
-        let malloc_size = sample_format_bytes * n_frames * n_channels;
-        Buffer {
-            data: unsafe { libc::malloc(malloc_size) as *mut libc::c_void },
+        let malloc_size = samples_len(frames_per_buffer, channel_count)?
+            .checked_mul(sample_format_bytes)
+            .ok_or(Error::BufferTooBig)?;
+        let data = unsafe { libc::malloc(malloc_size) as *mut libc::c_void };
+        if data.is_null() && malloc_size > 0 {
+            return Err(Error::InsufficientMemory);
         }
+        Ok(Buffer { data: data })
     }
 
     /// Convert the **Buffer**'s data field into a slice with the given format.
+    ///
+    /// The caller must ensure that `frames` does not exceed the number of frames with which the
+    /// **Buffer** was constructed.
     unsafe fn slice<'a, S>(&'a self, frames: u32, channels: i32) -> &'a [S] {
-        let len = (frames * channels as u32) as usize;
+        let len = frames as usize * channels as usize;
         // TODO: At the moment, we assume this buffer is interleaved. We need to check whether
         // or not buffer is interleaved here. This should probably an extra type parameter
         // (along-side the Sample type param).
@@ -963,8 +2009,11 @@ impl Buffer {
     }
 
     /// Convert the **Buffer**'s data field into a mutable slice with the given format.
+    ///
+    /// The caller must ensure that `frames` does not exceed the number of frames with which the
+    /// **Buffer** was constructed.
     unsafe fn slice_mut<'a, S>(&'a mut self, frames: u32, channels: i32) -> &'a mut [S] {
-        let len = (frames * channels as u32) as usize;
+        let len = frames as usize * channels as usize;
         // TODO: At the moment, we assume this buffer is interleaved. We need to check whether
         // or not buffer is interleaved here. This should probably an extra type parameter
         // (along-side the Sample type param).
@@ -978,6 +2027,29 @@ impl Drop for Buffer {
     }
 }
 
+/// The C parameters for one direction of a stream, or `None` if the direction has no channels.
+///
+/// PortAudio expects a direction without channels to be given as a null pointer, so this allows a
+/// **Duplex** stream with zero channels on one side to be opened as a half-duplex stream.
+fn direction_params<S: Sample>(params: Parameters<S>) -> Option<ffi::PaStreamParameters> {
+    if params.channel_count == 0 {
+        None
+    } else {
+        Some(params.into())
+    }
+}
+
+/// Returns `Err(Error::InvalidChannelCount)` if neither direction of a stream has any channels.
+fn check_directions(
+    in_params: &Option<ffi::PaStreamParameters>,
+    out_params: &Option<ffi::PaStreamParameters>,
+) -> Result<(), Error> {
+    if in_params.is_none() && out_params.is_none() {
+        return Err(Error::InvalidChannelCount);
+    }
+    Ok(())
+}
+
 fn open_blocking_stream(
     in_params: Option<ffi::PaStreamParameters>,
     out_params: Option<ffi::PaStreamParameters>,
@@ -985,6 +2057,7 @@ fn open_blocking_stream(
     frames_per_buffer: u32,
     flags: Flags,
 ) -> Result<*mut raw::c_void, Error> {
+    check_directions(&in_params, &out_params)?;
     // The pointer to which PortAudio will attach the stream.
     let mut c_stream_ptr: *mut raw::c_void = ptr::null_mut();
     let in_c_params = in_params.map(|p| p.into());
@@ -1027,6 +2100,7 @@ fn open_non_blocking_stream(
     flags: Flags,
     callback: &mut CallbackFnWrapper,
 ) -> Result<*mut raw::c_void, Error> {
+    check_directions(&in_params, &out_params)?;
     // The pointer to which PortAudio will attach the stream.
     let mut c_stream_ptr: *mut raw::c_void = ptr::null_mut();
     let in_c_params = in_params.map(|p| p.into());
@@ -1077,12 +2151,52 @@ fn open_non_blocking_stream(
 }
 
 impl<M, F> Stream<M, F> {
-    fn new_unopened(mode: M, flow: F, life: std::sync::Arc<super::Life>) -> Self {
+    fn new_unopened(
+        mode: M,
+        flow: F,
+        life: std::sync::Arc<super::Life>,
+        settings: OpenSettings,
+    ) -> Self {
         Stream {
             pa_stream: ptr::null_mut(),
             mode: mode,
             flow: flow,
             port_audio_life: life,
+            holds_slot: false,
+            settings: settings,
+            frame_totals: Arc::new(FrameTotals::default()),
+            callback_state: None,
+        }
+    }
+
+    /// Close the stream, returning the **Settings** with which it may be reopened.
+    ///
+    /// Any host API specific stream info is dropped from the **Flow**, as the structure it points
+    /// to may not outlive the original stream.
+    fn close_for_reopen(mut self) -> Result<(Reopen<F>, std::sync::Arc<super::Life>), Error>
+    where
+        F: Flow + Clone,
+    {
+        self.close()?;
+        let reopen = Reopen {
+            flow: self.flow.clone().without_host_api_specific_stream_info(),
+            settings: self.settings,
+        };
+        Ok((reopen, self.port_audio_life.clone()))
+    }
+
+    /// Claim a slot for the stream from the `PortAudio::set_max_concurrent_streams` limit.
+    fn acquire_slot(&mut self) -> Result<(), Error> {
+        self.port_audio_life.acquire_stream_slot()?;
+        self.holds_slot = true;
+        Ok(())
+    }
+
+    /// Return the stream's slot, if it holds one.
+    fn release_slot(&mut self) {
+        if self.holds_slot {
+            self.holds_slot = false;
+            self.port_audio_life.release_stream_slot();
         }
     }
 
@@ -1094,13 +2208,22 @@ impl<M, F> Stream<M, F> {
         let error_code = unsafe { ffi::Pa_CloseStream(self.pa_stream) };
         let error = FromPrimitive::from_i32(error_code).unwrap();
         match error {
-            Error::NoError => Ok(()),
+            Error::NoError => {
+                self.pa_stream = ptr::null_mut();
+                self.release_slot();
+                Ok(())
+            }
             err => Err(err),
         }
     }
 
     /// Commences audio processing.
+    ///
+    /// If de-click fades are enabled via **Stream::set_declick**, the output is faded in.
     pub fn start(&mut self) -> Result<(), Error> {
+        if let Some(ref state) = self.callback_state {
+            state.starting();
+        }
         let error_code = unsafe { ffi::Pa_StartStream(self.pa_stream) };
         let error = FromPrimitive::from_i32(error_code).unwrap();
         match error {
@@ -1112,7 +2235,20 @@ impl<M, F> Stream<M, F> {
     /// Terminates audio processing.
     ///
     /// It waits until all pending audio buffers have been played before it returns.
+    ///
+    /// If de-click fades are enabled via **Stream::set_declick**, the output is first faded out,
+    /// delaying the stop by the length of the fade.
     pub fn stop(&mut self) -> Result<(), Error> {
+        if let Some(ref state) = self.callback_state {
+            if let Ok(true) = self.is_active() {
+                state.stopping(self.settings.sample_rate);
+            }
+        }
+        self.stop_immediately()
+    }
+
+    /// Terminates audio processing without fading out, waiting for pending buffers to be played.
+    fn stop_immediately(&mut self) -> Result<(), Error> {
         let error_code = unsafe { ffi::Pa_StopStream(self.pa_stream) };
         let error = FromPrimitive::from_i32(error_code).unwrap();
         match error {
@@ -1195,11 +2331,30 @@ impl<M, F> Stream<M, F> {
     }
 
     /// Retrieve a Info structure containing information about the stream.
+    ///
+    /// Returns the default **Info**, whose `struct_version` is `0`, if PortAudio returns no info,
+    /// e.g. because the stream has been closed.
     pub fn info(&self) -> Info {
-        unsafe {
-            let info = ffi::Pa_GetStreamInfo(self.pa_stream);
-            Info::from(*info)
-        }
+        unsafe { Info::from_ptr(ffi::Pa_GetStreamInfo(self.pa_stream)) }
+    }
+
+    /// The total number of frames read from the stream since it was opened.
+    ///
+    /// This counts the frames returned by the blocking read methods, or the input frames passed to
+    /// the callback of a **NonBlocking** stream. Dividing it by the sample rate gives the duration
+    /// of audio captured so far. The total is kept atomically, so it is never torn when read
+    /// while a **StreamReader** or the stream's callback is running.
+    pub fn total_frames_read(&self) -> u64 {
+        self.frame_totals.read()
+    }
+
+    /// The total number of frames written to the stream since it was opened.
+    ///
+    /// This counts the frames passed to the blocking write methods, or the output frames produced
+    /// by the callback of a **NonBlocking** stream, including any silence output while paused.
+    /// Dividing it by the sample rate gives the position of playback, less the output latency.
+    pub fn total_frames_written(&self) -> u64 {
+        self.frame_totals.written()
     }
 
     /// This function is solely for use within the extension modules for interacting with PortAudio
@@ -1209,6 +2364,25 @@ impl<M, F> Stream<M, F> {
     }
 }
 
+impl<M, F> Stream<M, F>
+where
+    F: Flow,
+{
+    /// The sample format of the stream's input and output buffers respectively, or `None` for a
+    /// direction that the stream does not have.
+    ///
+    /// This is the format that the stream was actually opened with, rather than the format that
+    /// was first requested (e.g. when opened via **PortAudio::open_with_format_fallback**), and
+    /// includes the `NON_INTERLEAVED` flag if the buffers are non-interleaved. Use it to interpret
+    /// buffers at the byte level. Note that PortAudio converts between this format and the
+    /// device's native format internally and does not report the latter.
+    pub fn sample_format(&self) -> (Option<SampleFormatFlags>, Option<SampleFormatFlags>) {
+        let (in_params, out_params) = self.flow.params_both_directions();
+        let format = |params: ffi::PaStreamParameters| params.sampleFormat.into();
+        (in_params.map(format), out_params.map(format))
+    }
+}
+
 impl<F> Stream<Blocking<F::Buffer>, F>
 where
     F: Flow,
@@ -1219,10 +2393,22 @@ where
         S: Settings<Flow = F>,
     {
         let (flow, sample_rate, frames_per_buffer, flags) = settings.into_flow_and_settings();
-        let buffer = flow.new_buffer(frames_per_buffer);
-        let blocking = Blocking { buffer: buffer };
+        let buffer = flow.new_buffer(frames_per_buffer)?;
+        let blocking = Blocking {
+            buffer: buffer,
+            frames_per_buffer: frames_per_buffer,
+            input_overflows: ::std::cell::Cell::new(0),
+            #[cfg(feature = "async")]
+            write_waker: None,
+        };
         let (in_params, out_params) = flow.params_both_directions();
-        let mut stream = Stream::new_unopened(blocking, flow, life);
+        let open_settings = OpenSettings {
+            sample_rate: sample_rate,
+            frames_per_buffer: frames_per_buffer,
+            flags: flags,
+        };
+        let mut stream = Stream::new_unopened(blocking, flow, life, open_settings);
+        stream.acquire_slot()?;
         open_blocking_stream(in_params, out_params, sample_rate, frames_per_buffer, flags).map(
             |pa_stream| {
                 stream.pa_stream = pa_stream;
@@ -1230,6 +2416,27 @@ where
             },
         )
     }
+
+    /// Close the stream and reopen it with the same **Flow** and settings in **NonBlocking** mode,
+    /// driven by the given `callback`.
+    ///
+    /// **The existing stream is closed** (discarding any pending buffers as if **Stream::abort**
+    /// had been called) before the new one is opened, so audio is briefly interrupted and the new
+    /// stream is returned inactive (stopped). If reopening fails, both streams are lost and the
+    /// error is returned.
+    ///
+    /// Host API specific stream info attached to the **Parameters** is not carried over to the new
+    /// stream.
+    ///
+    /// See **PortAudio::open_non_blocking_stream** for details on the `callback`.
+    pub fn into_non_blocking<C>(self, callback: C) -> Result<Stream<NonBlocking, F>, Error>
+    where
+        F: Clone,
+        C: FnMut(F::CallbackArgs) -> ffi::PaStreamCallbackResult + 'static,
+    {
+        let (reopen, life) = self.close_for_reopen()?;
+        Stream::<NonBlocking, F>::open(life, reopen, callback)
+    }
 }
 
 impl<F> Stream<Blocking<F::Buffer>, F>
@@ -1247,15 +2454,7 @@ where
     ///
     /// See the blocking.rs example for a usage example.
     pub fn read_available(&self) -> Result<Available, Error> {
-        match unsafe { ffi::Pa_GetStreamReadAvailable(self.pa_stream) } {
-            n if n >= 0 => Ok(Available::Frames(n)),
-            n => match FromPrimitive::from_i64(n as i64) {
-                Some(Error::InputOverflowed) => Ok(Available::InputOverflowed),
-                Some(Error::OutputUnderflowed) => Ok(Available::OutputUnderflowed),
-                Some(err) => Err(err),
-                _ => panic!("Undefined error code: {:?}", n),
-            },
-        }
+        available_from(unsafe { ffi::Pa_GetStreamReadAvailable(self.pa_stream) })
     }
 
     /// Read samples from an input stream.
@@ -1268,10 +2467,146 @@ where
     ///
     /// Returns an interleaved slice containing the read audio data.
     ///
+    /// Returns `Err(Error::BufferTooBig)` if `frames` exceeds the `frames_per_buffer` with which
+    /// the stream was opened.
+    ///
     /// Returns an `Error` if some error occurred.
     ///
     /// TODO: Research and document exactly what errors can occur.
     pub fn read<'b>(&'b self, frames: u32) -> Result<&'b [F::Sample], Error> {
+        match self.read_allowing_overflow(frames)? {
+            (_, true) => Err(Error::InputOverflowed),
+            (samples, false) => Ok(samples),
+        }
+    }
+
+    /// The same as **Stream::read**, but gives up with `Err(Error::TimedOut)` if the requested
+    /// number of frames does not become available within the given `timeout`.
+    ///
+    /// PortAudio's own blocking read cannot be interrupted, so the timeout is implemented by
+    /// polling **Stream::read_available** and only reading once enough frames are available.
+    pub fn read_timeout<'b>(
+        &'b self,
+        frames: u32,
+        timeout: std::time::Duration,
+    ) -> Result<&'b [F::Sample], Error> {
+        if frames > self.mode.frames_per_buffer {
+            return Err(Error::BufferTooBig);
+        }
+        wait_until_available(frames, timeout, || self.read_available())?;
+        self.read(frames)
+    }
+
+    /// Read frames from an input stream until the given `predicate` returns `false` or
+    /// `max_frames` frames have been read.
+    ///
+    /// Frames are read in chunks of (at most) the `frames_per_buffer` with which the stream was
+    /// opened. Each chunk is appended to the returned buffer before being passed to the
+    /// `predicate`, so the chunk for which the `predicate` returns `false` is included in the
+    /// result. This is useful for "record until silence" style capture.
+    ///
+    /// Input overflow is not treated as an error: PortAudio still fills the buffer in this case, so
+    /// capture continues with a gap in the recorded signal.
+    ///
+    /// Returns the interleaved samples that were read.
+    ///
+    /// Returns `Err(Error::BufferTooSmall)` if the stream was opened with
+    /// `FRAMES_PER_BUFFER_UNSPECIFIED`, as the chunk size is unknown in this case.
+    pub fn capture_while<P>(
+        &self,
+        max_frames: u32,
+        mut predicate: P,
+    ) -> Result<Vec<F::Sample>, Error>
+    where
+        P: FnMut(&[F::Sample]) -> bool,
+        F::Sample: Clone,
+    {
+        let chunk_frames = self.mode.frames_per_buffer;
+        if chunk_frames == 0 {
+            return Err(Error::BufferTooSmall);
+        }
+        let channels = Reader::channel_count(&self.flow);
+        samples_len(max_frames, channels)?;
+        // The predicate may stop the capture long before `max_frames`, so only the first chunk is
+        // reserved up front.
+        let first_chunk = std::cmp::min(chunk_frames, max_frames);
+        let mut captured = Vec::with_capacity(samples_len(first_chunk, channels)?);
+        let mut remaining = max_frames;
+        while remaining > 0 {
+            let frames = std::cmp::min(chunk_frames, remaining);
+            let (samples, _overflowed) = self.read_allowing_overflow(frames)?;
+            captured.extend_from_slice(samples);
+            remaining -= frames;
+            if !predicate(samples) {
+                break;
+            }
+        }
+        Ok(captured)
+    }
+
+    /// Read the given number of frames from the stream into a new interleaved `Vec`.
+    ///
+    /// Unlike **Stream::read**, `frames` may exceed the `frames_per_buffer` with which the stream
+    /// was opened, in which case the stream is read in multiple chunks.
+    ///
+    /// Returns `Err(Error::InputOverflowed)` if input data was discarded by PortAudio while
+    /// reading.
+    pub fn read_frames(&self, frames: u32) -> Result<Vec<F::Sample>, Error>
+    where
+        F::Sample: Clone,
+    {
+        let chunk_frames = self.mode.frames_per_buffer;
+        if chunk_frames == 0 {
+            return Err(Error::BufferTooSmall);
+        }
+        let channels = Reader::channel_count(&self.flow);
+        let mut samples = Vec::with_capacity(samples_len(frames, channels)?);
+        let mut remaining = frames;
+        while remaining > 0 {
+            let frames = std::cmp::min(chunk_frames, remaining);
+            samples.extend_from_slice(self.read(frames)?);
+            remaining -= frames;
+        }
+        Ok(samples)
+    }
+
+    /// Record exactly `frames` frames from the stream into a new interleaved `Vec`.
+    ///
+    /// This is the simplest way to capture a fixed duration of audio, e.g. `sample_rate * 5.0`
+    /// frames for five seconds. Like **Stream::read_frames** the stream is read in chunks of the
+    /// `frames_per_buffer` with which it was opened, however input overflow is not treated as an
+    /// error. Instead, each chunk preceded by discarded input is counted and may be retrieved via
+    /// **Stream::input_overflow_count** once recording has finished.
+    ///
+    /// Returns `Err(Error::BufferTooSmall)` if the stream was opened with
+    /// `FRAMES_PER_BUFFER_UNSPECIFIED`, as the chunk size is unknown in this case.
+    pub fn record_to_vec(&self, frames: u32) -> Result<Vec<F::Sample>, Error>
+    where
+        F::Sample: Clone,
+    {
+        self.capture_while(frames, |_| true)
+    }
+
+    /// The number of reads for which PortAudio reported that input was discarded.
+    ///
+    /// This counts overflows tolerated by **Stream::record_to_vec** and **Stream::capture_while**
+    /// as well as those returned as `Err(Error::InputOverflowed)` by **Stream::read**.
+    pub fn input_overflow_count(&self) -> u64 {
+        self.mode.input_overflows.get()
+    }
+
+    /// Read `frames` frames into the stream's buffer.
+    ///
+    /// PortAudio still fills the buffer when it reports that the input has overflowed, so rather
+    /// than returning `Error::InputOverflowed` this returns the read samples alongside a `bool`
+    /// indicating whether or not input was discarded prior to them.
+    pub(crate) fn read_allowing_overflow<'b>(
+        &'b self,
+        frames: u32,
+    ) -> Result<(&'b [F::Sample], bool), Error> {
+        if frames > self.mode.frames_per_buffer {
+            return Err(Error::BufferTooBig);
+        }
         let buffer = F::readable_buffer(&self.mode);
         let err = unsafe {
             ffi::Pa_ReadStream(
@@ -1280,13 +2615,19 @@ where
                 frames as raw::c_ulong,
             )
         };
-        match err {
-            0 => unsafe {
-                let channel_count = Reader::channel_count(&self.flow);
-                Ok(buffer.slice(frames, channel_count))
-            },
-            err => Err(FromPrimitive::from_i32(err).unwrap()),
+        let overflowed = match err {
+            0 => false,
+            ffi::PaErrorCode_paInputOverflowed => true,
+            err => return Err(FromPrimitive::from_i32(err).unwrap()),
+        };
+        self.frame_totals.add_read(u64::from(frames));
+        if overflowed {
+            let overflows = &self.mode.input_overflows;
+            overflows.set(overflows.get() + 1);
         }
+        let channel_count = Reader::channel_count(&self.flow);
+        let samples = unsafe { buffer.slice(frames, channel_count) };
+        Ok((samples, overflowed))
     }
 }
 
@@ -1305,15 +2646,7 @@ where
     ///
     /// See the blocking.rs example for a usage example.
     pub fn write_available(&self) -> Result<Available, Error> {
-        match unsafe { ffi::Pa_GetStreamWriteAvailable(self.pa_stream) } {
-            n if n >= 0 => Ok(Available::Frames(n)),
-            n => match FromPrimitive::from_i64(n as i64) {
-                Some(Error::InputOverflowed) => Ok(Available::InputOverflowed),
-                Some(Error::OutputUnderflowed) => Ok(Available::OutputUnderflowed),
-                Some(err) => Err(err),
-                _ => panic!("Undefined error code: {:?}", n),
-            },
-        }
+        available_from(unsafe { ffi::Pa_GetStreamWriteAvailable(self.pa_stream) })
     }
 
     /// Write samples to an output stream.
@@ -1327,10 +2660,16 @@ where
     /// * write_fn - The buffer contains samples in the format specified by S.
     ///
     /// Returns Ok(()) on success and an Err(Error) variant on failure.
+    ///
+    /// Returns `Err(Error::BufferTooBig)` if `frames` exceeds the `frames_per_buffer` with which
+    /// the stream was opened.
     pub fn write<WF>(&mut self, frames: u32, write_fn: WF) -> Result<(), Error>
     where
         WF: for<'b> FnOnce(&'b mut [F::Sample]),
     {
+        if frames > self.mode.frames_per_buffer {
+            return Err(Error::BufferTooBig);
+        }
         let pa_stream = self.pa_stream;
         let channels = Writer::channel_count(&self.flow);
         let out_buffer = F::writable_buffer(&mut self.mode);
@@ -1343,92 +2682,1176 @@ where
             let written_slice_ptr = written_slice.as_ptr() as *mut raw::c_void;
             ffi::Pa_WriteStream(pa_stream, written_slice_ptr, frames as raw::c_ulong)
         };
+        // The frames are still written when PortAudio reports that the output had underflowed.
+        if result == 0 || result == ffi::PaErrorCode_paOutputUnderflowed {
+            self.frame_totals.add_written(u64::from(frames));
+        }
         match result {
             0 => Ok(()),
             err => Err(FromPrimitive::from_i32(err).unwrap()),
         }
     }
-}
 
-impl<F> Stream<NonBlocking, F> {
-    /// Open a new **NonBlocking** **Stream** with the given **Flow** and settings.
-    pub fn open<S, C>(
-        life: std::sync::Arc<super::Life>,
-        settings: S,
-        mut callback: C,
-    ) -> Result<Self, Error>
+    /// The same as **Stream::write**, but gives up with `Err(Error::TimedOut)` if space for the
+    /// requested number of frames does not become available within the given `timeout`.
+    ///
+    /// PortAudio's own blocking write cannot be interrupted, so the timeout is implemented by
+    /// polling **Stream::write_available** and only writing once enough space is available. The
+    /// `write_fn` is not called if the timeout elapses.
+    pub fn write_timeout<WF>(
+        &mut self,
+        frames: u32,
+        timeout: std::time::Duration,
+        write_fn: WF,
+    ) -> Result<(), Error>
     where
-        S: Settings<Flow = F>,
-        F: Flow,
-        C: FnMut(F::CallbackArgs) -> ffi::PaStreamCallbackResult + 'static,
+        WF: for<'b> FnOnce(&'b mut [F::Sample]),
     {
-        let (flow, sample_rate, frames_per_buffer, flags) = settings.into_flow_and_settings();
-        let (in_params, out_params) = flow.params_both_directions();
-        let in_channels = in_params.map(|p| p.channelCount).unwrap_or(0);
-        let out_channels = out_params.map(|p| p.channelCount).unwrap_or(0);
-
-        let callback_wrapper_fn = move |input: *const raw::c_void,
-                                        output: *mut raw::c_void,
-                                        frame_count: raw::c_ulong,
-                                        time_info: *const ffi::PaStreamCallbackTimeInfo,
-                                        flags: ffi::PaStreamCallbackFlags|
-              -> ffi::PaStreamCallbackResult {
-            let args = F::new_callback_args(
-                input,
-                output,
-                frame_count,
-                time_info,
-                flags,
-                in_channels,
-                out_channels,
-            );
-            callback(args)
-        };
-
-        let non_blocking = NonBlocking {
-            // Here, we `Box` the wrapper so that we can collect the pointer from the callback.
-            //
-            // TODO: See if it is possible to pass a ptr to the callback_fn itself instead of
-            // requiring the wrapper at all. It seems like DST will be a problem here though.
-            callback: Box::new(CallbackFnWrapper {
-                // Here we `Box` the callback fn as we can't handle generic types in the c callback
-                // function.
-                f: Box::new(callback_wrapper_fn),
-            }),
-        };
+        if frames > self.mode.frames_per_buffer {
+            return Err(Error::BufferTooBig);
+        }
+        wait_until_available(frames, timeout, || self.write_available())?;
+        self.write(frames, write_fn)
+    }
 
-        let mut stream = Stream::new_unopened(non_blocking, flow, life);
-        open_non_blocking_stream(
-            in_params,
-            out_params,
-            sample_rate,
-            frames_per_buffer,
-            flags,
-            &mut stream.mode.callback,
-        )
-        .map(|pa_stream| {
-            stream.pa_stream = pa_stream;
-            stream
-        })
+    /// Wait until all audio written to the stream has been played, but no longer than `timeout`.
+    ///
+    /// Returns `true` if the stream drained in time or `false` if the `timeout` elapsed first.
+    ///
+    /// PortAudio does not report the capacity of the stream's output buffer, so it is estimated
+    /// from the output latency reported by **Stream::info** (and is at least the
+    /// `frames_per_buffer` with which the stream was opened). The stream is considered drained once
+    /// **Stream::write_available** reaches this capacity or reports an output underflow. Should the
+    /// estimate exceed the real capacity this errs on the side of waiting out the `timeout`, rather
+    /// than returning before the audio has played.
+    pub fn drain_timeout(&self, timeout: std::time::Duration) -> Result<bool, Error> {
+        let info = self.info();
+        let latency_frames = (info.output_latency * info.sample_rate).round() as u32;
+        let capacity = std::cmp::max(latency_frames, self.mode.frames_per_buffer);
+        wait_until_drained(capacity, timeout, || self.write_available())
     }
 
-    /// Retrieve CPU usage information for the specified stream.
+    /// Write the whole of the given interleaved `buffer` to the stream.
     ///
-    /// The "CPU Load" is a fraction of total CPU time consumed by a callback stream's audio
-    /// processing routines including, but not limited to the client supplied stream callback.
-    pub fn cpu_load(&self) -> f64 {
-        unsafe { ffi::Pa_GetStreamCpuLoad(self.pa_stream) }
+    /// The number of frames is inferred from the length of the `buffer` and the stream's channel
+    /// count. Unlike **Stream::write**, the `buffer` may contain more than the `frames_per_buffer`
+    /// with which the stream was opened, in which case it is written in multiple chunks.
+    ///
+    /// Returns `Err(Error::InvalidChannelCount)` if the length of the `buffer` is not a multiple of
+    /// the stream's channel count, in which case nothing is written.
+    pub fn write_frames(&mut self, buffer: &[F::Sample]) -> Result<(), Error>
+    where
+        F::Sample: Copy,
+    {
+        let channels = Writer::channel_count(&self.flow);
+        if channels <= 0 || buffer.len() % channels as usize != 0 {
+            return Err(Error::InvalidChannelCount);
+        }
+        let chunk_frames = self.mode.frames_per_buffer;
+        if chunk_frames == 0 {
+            return Err(Error::BufferTooSmall);
+        }
+        let chunk_len = samples_len(chunk_frames, channels)?;
+        for chunk in buffer.chunks(chunk_len) {
+            let frames = (chunk.len() / channels as usize) as u32;
+            self.write(frames, |slice| slice.copy_from_slice(chunk))?;
+        }
+        Ok(())
     }
-}
 
-impl<M, F> Drop for Stream<M, F> {
-    fn drop(&mut self) {
-        self.stop().ok();
-        self.close().ok();
+    /// Write `frames` frames of silence to the stream, e.g. to prime it or to insert a gap between
+    /// clips.
+    ///
+    /// The silence is written via the stream's own buffer, so no allocation is performed. Like
+    /// **Stream::write_frames**, `frames` may exceed the `frames_per_buffer` with which the stream
+    /// was opened, in which case it is written in multiple chunks.
+    pub fn write_silence(&mut self, frames: u32) -> Result<(), Error>
+    where
+        F::Sample: Sample,
+    {
+        let chunk_frames = self.mode.frames_per_buffer;
+        if chunk_frames == 0 {
+            return Err(Error::BufferTooSmall);
+        }
+        let mut remaining = frames;
+        while remaining > 0 {
+            let frames = std::cmp::min(chunk_frames, remaining);
+            self.write(frames, fill_silence)?;
+            remaining -= frames;
+        }
+        Ok(())
     }
-}
 
-/// A callback procedure to be used by portaudio in the case that a user_callback has been given
+    /// Write as many frames of the given interleaved `samples` as the stream has room for without
+    /// blocking, returning the number of frames written.
+    ///
+    /// At most `frames_per_buffer` frames (as given when the stream was opened) are written per
+    /// call. Returns `Poll::Pending` if there is not yet room for the smaller of the `samples` and
+    /// `frames_per_buffer`, in which case the task is woken once the stream is expected to have
+    /// played enough audio to make room.
+    ///
+    /// PortAudio gives no notification when a blocking stream's buffer drains, so the waking is
+    /// driven by a timer thread that is spawned the first time this returns `Poll::Pending` and
+    /// lives as long as the stream. The task is woken after the time it takes to play the missing
+    /// frames at the stream's sample rate, so it is usually woken within a millisecond or so of
+    /// room becoming available, plus the scheduling latency of the timer thread and the async
+    /// runtime. The stream's output latency should be high enough to absorb this delay. A task
+    /// that is woken before there is room simply returns `Poll::Pending` again.
+    ///
+    /// Returns `Err(Error::BufferTooSmall)` if the stream was opened with an unspecified
+    /// `frames_per_buffer`. Only available with the `async` feature.
+    #[cfg(feature = "async")]
+    pub fn poll_write(
+        &mut self,
+        cx: &mut std::task::Context,
+        samples: &[F::Sample],
+    ) -> std::task::Poll<Result<usize, Error>>
+    where
+        F::Sample: Copy,
+    {
+        use std::task::Poll;
+        let channels = Writer::channel_count(&self.flow);
+        if channels <= 0 {
+            return Poll::Ready(Err(Error::InvalidChannelCount));
+        }
+        let chunk_frames = self.mode.frames_per_buffer as usize;
+        if chunk_frames == 0 {
+            return Poll::Ready(Err(Error::BufferTooSmall));
+        }
+        let frames = std::cmp::min(samples.len() / channels as usize, chunk_frames);
+        if frames == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let available = match self.write_available() {
+            Ok(Available::Frames(available)) => std::cmp::max(available, 0) as usize,
+            // An underflowed output has drained entirely.
+            Ok(_) => frames,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+        if available < frames {
+            let missing = (frames - available) as f64 / self.settings.sample_rate;
+            let at = std::time::Instant::now() + std::time::Duration::from_secs_f64(missing);
+            if self.mode.write_waker.is_none() {
+                self.mode.write_waker = WakeTimer::spawn();
+            }
+            match self.mode.write_waker {
+                Some(ref timer) => timer.wake_at(cx.waker().clone(), at),
+                // Without a timer thread the task can only poll again straight away.
+                None => cx.waker().wake_by_ref(),
+            }
+            return Poll::Pending;
+        }
+        let len = frames * channels as usize;
+        let result = self.write(frames as u32, |slice| {
+            slice.copy_from_slice(&samples[..len])
+        });
+        Poll::Ready(result.map(|()| frames))
+    }
+}
+
+/// A thread that wakes a task once a deadline has passed, used to drive **Stream::poll_write**.
+///
+/// The thread exits when the **WakeTimer** is dropped.
+#[cfg(feature = "async")]
+struct WakeTimer {
+    shared: Arc<WakeTimerShared>,
+}
+
+#[cfg(feature = "async")]
+struct WakeTimerShared {
+    state: std::sync::Mutex<WakeTimerState>,
+    condvar: std::sync::Condvar,
+}
+
+#[cfg(feature = "async")]
+#[derive(Default)]
+struct WakeTimerState {
+    /// The waker of the most recently parked task, along with when it should be woken.
+    pending: Option<(std::task::Waker, std::time::Instant)>,
+    /// Set when the **WakeTimer** is dropped.
+    shutdown: bool,
+}
+
+#[cfg(feature = "async")]
+impl WakeTimer {
+    /// Spawn the timer's thread, or return `None` if it could not be spawned.
+    fn spawn() -> Option<Self> {
+        let shared = Arc::new(WakeTimerShared {
+            state: std::sync::Mutex::new(WakeTimerState::default()),
+            condvar: std::sync::Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        let builder = std::thread::Builder::new().name("portaudio-write-waker".to_string());
+        builder.spawn(move || thread_shared.run()).ok()?;
+        Some(WakeTimer { shared: shared })
+    }
+
+    /// Wake the given `waker` at the given instant, replacing any waker that is already pending.
+    fn wake_at(&self, waker: std::task::Waker, at: std::time::Instant) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.pending = Some((waker, at));
+        self.shared.condvar.notify_one();
+    }
+}
+
+#[cfg(feature = "async")]
+impl WakeTimerShared {
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.shutdown {
+            let at = match state.pending {
+                Some((_, at)) => at,
+                None => {
+                    state = self.condvar.wait(state).unwrap();
+                    continue;
+                }
+            };
+            let now = std::time::Instant::now();
+            if now < at {
+                state = self.condvar.wait_timeout(state, at - now).unwrap().0;
+            } else if let Some((waker, _)) = state.pending.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for WakeTimer {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.condvar.notify_one();
+    }
+}
+
+/// Poll `available` until `capacity` frames may be written, i.e. the output buffer has drained.
+///
+/// Returns `false` if the `timeout` elapses first.
+fn wait_until_drained<A>(
+    capacity: u32,
+    timeout: std::time::Duration,
+    available: A,
+) -> Result<bool, Error>
+where
+    A: FnMut() -> Result<Available, Error>,
+{
+    match wait_until_available(capacity, timeout, available) {
+        Ok(()) => Ok(true),
+        Err(Error::TimedOut) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Poll the given `available` function until at least `frames` frames are available or the given
+/// `timeout` elapses, in which case `Err(Error::TimedOut)` is returned.
+///
+/// Overflow and underflow flags end the wait so that the subsequent read or write may report them.
+fn wait_until_available<A>(
+    frames: u32,
+    timeout: std::time::Duration,
+    mut available: A,
+) -> Result<(), Error>
+where
+    A: FnMut() -> Result<Available, Error>,
+{
+    // Short enough to add little latency, long enough to avoid spinning on the CPU.
+    let poll_interval = std::time::Duration::from_millis(1);
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        match available()? {
+            Available::Frames(n) if n >= frames as raw::c_long => return Ok(()),
+            Available::Frames(_) => (),
+            Available::InputOverflowed | Available::OutputUnderflowed => return Ok(()),
+        }
+        let now = std::time::Instant::now();
+        if now >= deadline {
+            return Err(Error::TimedOut);
+        }
+        std::thread::sleep(std::cmp::min(poll_interval, deadline - now));
+    }
+}
+
+impl<M, I> Stream<M, Input<I>>
+where
+    I: Sample,
+{
+    /// The number of samples within a single frame, i.e. the channel count.
+    pub fn samples_per_frame(&self) -> usize {
+        self.flow.params.samples_per_frame()
+    }
+
+    /// The number of bytes occupied by a single frame.
+    ///
+    /// See **Parameters::bytes_per_frame**.
+    pub fn bytes_per_frame(&self) -> usize {
+        self.flow.params.bytes_per_frame()
+    }
+}
+
+impl<M, O> Stream<M, Output<O>>
+where
+    O: Sample,
+{
+    /// The number of samples within a single frame, i.e. the channel count.
+    pub fn samples_per_frame(&self) -> usize {
+        self.flow.params.samples_per_frame()
+    }
+
+    /// The number of bytes occupied by a single frame.
+    ///
+    /// See **Parameters::bytes_per_frame**.
+    pub fn bytes_per_frame(&self) -> usize {
+        self.flow.params.bytes_per_frame()
+    }
+}
+
+impl<M, I, O> Stream<M, Duplex<I, O>>
+where
+    I: Sample,
+    O: Sample,
+{
+    /// The number of samples within a single input frame, i.e. the input channel count.
+    pub fn in_samples_per_frame(&self) -> usize {
+        self.flow.in_params.samples_per_frame()
+    }
+
+    /// The number of bytes occupied by a single input frame.
+    ///
+    /// See **Parameters::bytes_per_frame**.
+    pub fn in_bytes_per_frame(&self) -> usize {
+        self.flow.in_params.bytes_per_frame()
+    }
+
+    /// The number of samples within a single output frame, i.e. the output channel count.
+    pub fn out_samples_per_frame(&self) -> usize {
+        self.flow.out_params.samples_per_frame()
+    }
+
+    /// The number of bytes occupied by a single output frame.
+    ///
+    /// See **Parameters::bytes_per_frame**.
+    pub fn out_bytes_per_frame(&self) -> usize {
+        self.flow.out_params.bytes_per_frame()
+    }
+}
+
+impl Stream<Blocking<()>, Raw> {
+    /// Open a new **Blocking** **Stream** with the given raw parameters.
+    ///
+    /// # Safety
+    ///
+    /// See **PortAudio::open_blocking_stream_raw**.
+    pub unsafe fn open_raw(
+        life: std::sync::Arc<super::Life>,
+        in_params: Option<ffi::PaStreamParameters>,
+        out_params: Option<ffi::PaStreamParameters>,
+        sample_rate: f64,
+        frames_per_buffer: u32,
+        flags: Flags,
+    ) -> Result<Self, Error> {
+        let blocking = Blocking {
+            buffer: (),
+            frames_per_buffer: frames_per_buffer,
+            input_overflows: ::std::cell::Cell::new(0),
+            #[cfg(feature = "async")]
+            write_waker: None,
+        };
+        let flow = Raw {
+            in_params: in_params,
+            out_params: out_params,
+        };
+        let open_settings = OpenSettings {
+            sample_rate: sample_rate,
+            frames_per_buffer: frames_per_buffer,
+            flags: flags,
+        };
+        let mut stream = Stream::new_unopened(blocking, flow, life, open_settings);
+        stream.acquire_slot()?;
+        open_blocking_stream(in_params, out_params, sample_rate, frames_per_buffer, flags).map(
+            |pa_stream| {
+                stream.pa_stream = pa_stream;
+                stream
+            },
+        )
+    }
+
+    /// Retrieve the number of frames that can be read from the stream without waiting.
+    ///
+    /// See **Stream::read_available**.
+    pub fn read_available(&self) -> Result<Available, Error> {
+        available_from(unsafe { ffi::Pa_GetStreamReadAvailable(self.pa_stream) })
+    }
+
+    /// Retrieve the number of frames that can be written to the stream without waiting.
+    ///
+    /// See **Stream::write_available**.
+    pub fn write_available(&self) -> Result<Available, Error> {
+        available_from(unsafe { ffi::Pa_GetStreamWriteAvailable(self.pa_stream) })
+    }
+
+    /// Read `frames` frames from the stream's input into the given `buffer` using
+    /// `Pa_ReadStream`.
+    ///
+    /// Returns `Err(Error::InputOverflowed)` if input data was discarded prior to the read. The
+    /// `buffer` is filled regardless.
+    ///
+    /// # Safety
+    ///
+    /// The `buffer` must be laid out as described by the input `PaStreamParameters` with which the
+    /// stream was opened (i.e. their sample format, channel count and interleaving) and must be
+    /// large enough to hold `frames` frames.
+    pub unsafe fn read_raw(&self, buffer: *mut raw::c_void, frames: u32) -> Result<(), Error> {
+        let result = ffi::Pa_ReadStream(self.pa_stream, buffer, frames as raw::c_ulong);
+        if result == 0 || result == ffi::PaErrorCode_paInputOverflowed {
+            self.frame_totals.add_read(u64::from(frames));
+        }
+        match result {
+            0 => Ok(()),
+            err => Err(FromPrimitive::from_i32(err).unwrap()),
+        }
+    }
+
+    /// Write `frames` frames from the given `buffer` to the stream's output using
+    /// `Pa_WriteStream`.
+    ///
+    /// # Safety
+    ///
+    /// The `buffer` must be laid out as described by the output `PaStreamParameters` with which
+    /// the stream was opened (i.e. their sample format, channel count and interleaving) and must
+    /// contain at least `frames` frames.
+    pub unsafe fn write_raw(&self, buffer: *const raw::c_void, frames: u32) -> Result<(), Error> {
+        let result = ffi::Pa_WriteStream(self.pa_stream, buffer, frames as raw::c_ulong);
+        if result == 0 || result == ffi::PaErrorCode_paOutputUnderflowed {
+            self.frame_totals.add_written(u64::from(frames));
+        }
+        match result {
+            0 => Ok(()),
+            err => Err(FromPrimitive::from_i32(err).unwrap()),
+        }
+    }
+}
+
+/// Convert the result of `Pa_GetStreamReadAvailable` or `Pa_GetStreamWriteAvailable` into an
+/// **Available**.
+fn available_from(n: raw::c_long) -> Result<Available, Error> {
+    match n {
+        n if n >= 0 => Ok(Available::Frames(n)),
+        n => match FromPrimitive::from_i64(n as i64) {
+            Some(Error::InputOverflowed) => Ok(Available::InputOverflowed),
+            Some(Error::OutputUnderflowed) => Ok(Available::OutputUnderflowed),
+            Some(err) => Err(err),
+            _ => panic!("Undefined error code: {:?}", n),
+        },
+    }
+}
+
+/// A view of the input side of a **Blocking** **Duplex** **Stream**.
+///
+/// Only the reading methods are exposed, preventing accidental writes to the stream's output. See
+/// **Stream::input**.
+pub struct DuplexInput<'s, I: 's, O: 's> {
+    stream: &'s mut Stream<Blocking<(Buffer, Buffer)>, Duplex<I, O>>,
+}
+
+/// A view of the output side of a **Blocking** **Duplex** **Stream**.
+///
+/// Only the writing methods are exposed, preventing accidental reads from the stream's input. See
+/// **Stream::output**.
+pub struct DuplexOutput<'s, I: 's, O: 's> {
+    stream: &'s mut Stream<Blocking<(Buffer, Buffer)>, Duplex<I, O>>,
+}
+
+impl<I, O> Stream<Blocking<(Buffer, Buffer)>, Duplex<I, O>>
+where
+    I: Sample + 'static,
+    O: Sample + 'static,
+{
+    /// Borrow a view of the stream that may only be read from.
+    ///
+    /// This is useful for passing the input side of a duplex stream to helpers that should only
+    /// be able to capture audio.
+    pub fn input<'s>(&'s mut self) -> DuplexInput<'s, I, O> {
+        DuplexInput { stream: self }
+    }
+
+    /// Borrow a view of the stream that may only be written to.
+    ///
+    /// This is useful for passing the output side of a duplex stream to helpers that should only
+    /// be able to play back audio.
+    pub fn output<'s>(&'s mut self) -> DuplexOutput<'s, I, O> {
+        DuplexOutput { stream: self }
+    }
+}
+
+impl<'s, I, O> DuplexInput<'s, I, O>
+where
+    I: Sample + 'static,
+    O: Sample + 'static,
+{
+    /// Retrieve the number of frames that can be read from the stream without waiting.
+    ///
+    /// See **Stream::read_available**.
+    pub fn read_available(&self) -> Result<Available, Error> {
+        self.stream.read_available()
+    }
+
+    /// Read samples from the stream's input.
+    ///
+    /// See **Stream::read**.
+    pub fn read(&self, frames: u32) -> Result<&[I], Error> {
+        self.stream.read(frames)
+    }
+
+    /// Read samples from the stream's input, giving up if they don't arrive within `timeout`.
+    ///
+    /// See **Stream::read_timeout**.
+    pub fn read_timeout(&self, frames: u32, timeout: std::time::Duration) -> Result<&[I], Error> {
+        self.stream.read_timeout(frames, timeout)
+    }
+
+    /// Read the given number of frames from the stream's input into a new `Vec`.
+    ///
+    /// See **Stream::read_frames**.
+    pub fn read_frames(&self, frames: u32) -> Result<Vec<I>, Error> {
+        self.stream.read_frames(frames)
+    }
+
+    /// Record exactly `frames` frames from the stream's input into a new `Vec`.
+    ///
+    /// See **Stream::record_to_vec**.
+    pub fn record_to_vec(&self, frames: u32) -> Result<Vec<I>, Error> {
+        self.stream.record_to_vec(frames)
+    }
+
+    /// The number of reads for which input was discarded.
+    ///
+    /// See **Stream::input_overflow_count**.
+    pub fn input_overflow_count(&self) -> u64 {
+        self.stream.input_overflow_count()
+    }
+}
+
+impl<'s, I, O> DuplexOutput<'s, I, O>
+where
+    I: Sample + 'static,
+    O: Sample + 'static,
+{
+    /// Retrieve the number of frames that can be written to the stream without waiting.
+    ///
+    /// See **Stream::write_available**.
+    pub fn write_available(&self) -> Result<Available, Error> {
+        self.stream.write_available()
+    }
+
+    /// Write samples to the stream's output.
+    ///
+    /// See **Stream::write**.
+    pub fn write<WF>(&mut self, frames: u32, write_fn: WF) -> Result<(), Error>
+    where
+        WF: for<'b> FnOnce(&'b mut [O]),
+    {
+        self.stream.write(frames, write_fn)
+    }
+
+    /// Write samples to the stream's output, giving up if space doesn't become available within
+    /// `timeout`.
+    ///
+    /// See **Stream::write_timeout**.
+    pub fn write_timeout<WF>(
+        &mut self,
+        frames: u32,
+        timeout: std::time::Duration,
+        write_fn: WF,
+    ) -> Result<(), Error>
+    where
+        WF: for<'b> FnOnce(&'b mut [O]),
+    {
+        self.stream.write_timeout(frames, timeout, write_fn)
+    }
+
+    /// Wait until all audio written to the stream's output has been played, but no longer than
+    /// `timeout`.
+    ///
+    /// See **Stream::drain_timeout**.
+    pub fn drain_timeout(&self, timeout: std::time::Duration) -> Result<bool, Error> {
+        self.stream.drain_timeout(timeout)
+    }
+
+    /// Write the whole of the given buffer to the stream's output.
+    ///
+    /// See **Stream::write_frames**.
+    pub fn write_frames(&mut self, buffer: &[O]) -> Result<(), Error> {
+        self.stream.write_frames(buffer)
+    }
+
+    /// Write `frames` frames of silence to the stream's output.
+    ///
+    /// See **Stream::write_silence**.
+    pub fn write_silence(&mut self, frames: u32) -> Result<(), Error> {
+        self.stream.write_silence(frames)
+    }
+
+    /// Write samples to the stream's output if there is room, without blocking.
+    ///
+    /// See **Stream::poll_write**.
+    #[cfg(feature = "async")]
+    pub fn poll_write(
+        &mut self,
+        cx: &mut std::task::Context,
+        samples: &[O],
+    ) -> std::task::Poll<Result<usize, Error>> {
+        self.stream.poll_write(cx, samples)
+    }
+}
+
+/// The reading half of a **Blocking** **Stream**, produced by **Stream::split**.
+///
+/// The reader may be sent to another thread, e.g. a capture worker, while the stream is controlled
+/// via the **StreamController**.
+pub struct StreamReader<F>
+where
+    F: Flow + Reader,
+{
+    shared: Arc<SplitStream<F>>,
+}
+
+/// The controlling half of a **Blocking** **Stream**, produced by **Stream::split**.
+pub struct StreamController<F>
+where
+    F: Flow + Reader,
+{
+    shared: Arc<SplitStream<F>>,
+}
+
+/// The state shared between a **StreamReader** and a **StreamController**.
+///
+/// PortAudio does not allow a blocking stream to be stopped while it is being read from another
+/// thread, so every operation locks the stream.
+struct SplitStream<F>
+where
+    F: Flow + Reader,
+{
+    stream: Mutex<Stream<Blocking<F::Buffer>, F>>,
+    /// The number of **StreamController** operations waiting for the lock. The reader yields to
+    /// these between reads, so that a reader reading in a loop cannot starve the controller.
+    pending_control: AtomicUsize,
+    /// The stream's frame totals, which may be read without locking the stream.
+    frame_totals: Arc<FrameTotals>,
+}
+
+impl<F> SplitStream<F>
+where
+    F: Flow + Reader,
+{
+    fn lock(&self) -> std::sync::MutexGuard<'_, Stream<Blocking<F::Buffer>, F>> {
+        match self.stream.lock() {
+            Ok(stream) => stream,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Lock the stream on behalf of the **StreamController**, ahead of the reader.
+    fn control<T, C>(&self, control: C) -> T
+    where
+        C: FnOnce(&mut Stream<Blocking<F::Buffer>, F>) -> T,
+    {
+        self.pending_control.fetch_add(1, Ordering::SeqCst);
+        let result = control(&mut self.lock());
+        self.pending_control.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+}
+
+impl<F> Stream<Blocking<F::Buffer>, F>
+where
+    F: Flow + Reader,
+{
+    /// Split the stream into a **StreamReader** that reads from the stream and a
+    /// **StreamController** that starts, stops and queries it.
+    ///
+    /// This allows the reader to be moved to a worker thread while the stream is controlled from
+    /// another, e.g. the main thread. Both halves are `Send` and `Sync` and the stream is closed
+    /// once both have been dropped.
+    ///
+    /// The halves share the stream behind a lock, as PortAudio does not allow a blocking stream
+    /// to be used from multiple threads at once. Each **StreamReader::read** holds the lock until
+    /// the requested frames have been read, so an operation on the **StreamController** waits for
+    /// at most the duration of one read to complete. Reads started while a controller operation
+    /// is waiting are deferred until it has finished. To keep the controller responsive, read at
+    /// most a buffer's worth of frames at a time.
+    pub fn split(self) -> (StreamReader<F>, StreamController<F>) {
+        let frame_totals = self.frame_totals.clone();
+        let shared = Arc::new(SplitStream {
+            stream: Mutex::new(self),
+            pending_control: AtomicUsize::new(0),
+            frame_totals: frame_totals,
+        });
+        let reader = StreamReader {
+            shared: shared.clone(),
+        };
+        (reader, StreamController { shared: shared })
+    }
+}
+
+impl<F> StreamReader<F>
+where
+    F: Flow + Reader,
+{
+    /// Read `frames` frames from the stream and pass the interleaved samples to `read_fn`,
+    /// returning its result.
+    ///
+    /// The samples are only borrowed for the duration of `read_fn` as the stream is locked while
+    /// reading. See **Stream::read** for the possible errors.
+    pub fn read<R, RF>(&self, frames: u32, read_fn: RF) -> Result<R, Error>
+    where
+        RF: FnOnce(&[F::Sample]) -> R,
+    {
+        while self.shared.pending_control.load(Ordering::SeqCst) > 0 {
+            std::thread::yield_now();
+        }
+        let stream = self.shared.lock();
+        stream.read(frames).map(read_fn)
+    }
+
+    /// Retrieve the number of frames that can be read without waiting.
+    ///
+    /// See **Stream::read_available**.
+    pub fn read_available(&self) -> Result<Available, Error> {
+        self.shared.lock().read_available()
+    }
+}
+
+impl<F> StreamController<F>
+where
+    F: Flow + Reader,
+{
+    /// Start the stream.
+    ///
+    /// See **Stream::start**.
+    pub fn start(&self) -> Result<(), Error> {
+        self.shared.control(|stream| stream.start())
+    }
+
+    /// Stop the stream once any read in progress has finished.
+    ///
+    /// See **Stream::stop**.
+    pub fn stop(&self) -> Result<(), Error> {
+        self.shared.control(|stream| stream.stop())
+    }
+
+    /// Abort the stream once any read in progress has finished.
+    ///
+    /// See **Stream::abort**.
+    pub fn abort(&self) -> Result<(), Error> {
+        self.shared.control(|stream| stream.abort())
+    }
+
+    /// Whether or not the stream is currently active.
+    ///
+    /// See **Stream::is_active**.
+    pub fn is_active(&self) -> Result<bool, Error> {
+        self.shared.control(|stream| stream.is_active())
+    }
+
+    /// Whether or not the stream is currently stopped.
+    ///
+    /// See **Stream::is_stopped**.
+    pub fn is_stopped(&self) -> Result<bool, Error> {
+        self.shared.control(|stream| stream.is_stopped())
+    }
+
+    /// Retrieve the stream's latency and sample rate.
+    ///
+    /// See **Stream::info**.
+    pub fn info(&self) -> Info {
+        self.shared.control(|stream| stream.info())
+    }
+
+    /// The total number of frames read by the **StreamReader** so far.
+    ///
+    /// Unlike the other methods this does not wait for a read in progress, so it may be polled
+    /// for progress reporting. See **Stream::total_frames_read**.
+    pub fn total_frames_read(&self) -> u64 {
+        self.shared.frame_totals.read()
+    }
+}
+
+impl<F> Stream<NonBlocking, F> {
+    /// Open a new **NonBlocking** **Stream** with the given **Flow** and settings.
+    pub fn open<S, C>(
+        life: std::sync::Arc<super::Life>,
+        settings: S,
+        mut callback: C,
+    ) -> Result<Self, Error>
+    where
+        S: Settings<Flow = F>,
+        F: Flow,
+        C: FnMut(F::CallbackArgs) -> ffi::PaStreamCallbackResult + 'static,
+    {
+        let (flow, sample_rate, frames_per_buffer, flags) = settings.into_flow_and_settings();
+        let (in_params, out_params) = flow.params_both_directions();
+        let in_channels = in_params.map(|p| p.channelCount).unwrap_or(0);
+        let out_channels = out_params.map(|p| p.channelCount).unwrap_or(0);
+        let in_format = in_params
+            .map(|p| p.sampleFormat.into())
+            .unwrap_or_else(SampleFormatFlags::empty);
+        let out_format = out_params
+            .map(|p| p.sampleFormat.into())
+            .unwrap_or_else(SampleFormatFlags::empty);
+        let state = Arc::new(CallbackState::default());
+        let output_mutes: Arc<Vec<_>> =
+            Arc::new((0..out_channels).map(|_| AtomicBool::new(false)).collect());
+        let frame_totals = Arc::new(FrameTotals::default());
+
+        let callback_wrapper_fn = move |input: *const raw::c_void,
+                                        output: *mut raw::c_void,
+                                        frame_count: raw::c_ulong,
+                                        time_info: *const ffi::PaStreamCallbackTimeInfo,
+                                        flags: ffi::PaStreamCallbackFlags,
+                                        in_channels: i32,
+                                        out_channels: i32|
+              -> ffi::PaStreamCallbackResult {
+            let args = F::new_callback_args(
+                input,
+                output,
+                frame_count,
+                time_info,
+                flags,
+                in_channels,
+                out_channels,
+            );
+            callback(args)
+        };
+
+        let non_blocking = NonBlocking {
+            // Here, we `Box` the wrapper so that we can collect the pointer from the callback.
+            //
+            // TODO: See if it is possible to pass a ptr to the callback_fn itself instead of
+            // requiring the wrapper at all. It seems like DST will be a problem here though.
+            callback: Box::new(CallbackFnWrapper {
+                // Here we `Box` the callback fn as we can't handle generic types in the c callback
+                // function.
+                f: Box::new(callback_wrapper_fn),
+                in_channels: in_channels,
+                out_channels: out_channels,
+                in_format: in_format,
+                out_format: out_format,
+                state: state.clone(),
+                frame_totals: frame_totals.clone(),
+                output_mutes: output_mutes.clone(),
+                is_paused: false,
+                fade: None,
+            }),
+            state: state.clone(),
+            output_mutes: output_mutes,
+        };
+
+        let open_settings = OpenSettings {
+            sample_rate: sample_rate,
+            frames_per_buffer: frames_per_buffer,
+            flags: flags,
+        };
+        let mut stream = Stream::new_unopened(non_blocking, flow, life, open_settings);
+        stream.frame_totals = frame_totals;
+        stream.callback_state = Some(state);
+        stream.acquire_slot()?;
+        open_non_blocking_stream(
+            in_params,
+            out_params,
+            sample_rate,
+            frames_per_buffer,
+            flags,
+            &mut stream.mode.callback,
+        )
+        .map(|pa_stream| {
+            stream.pa_stream = pa_stream;
+            stream
+        })
+    }
+
+    /// Close the stream and reopen it with the same **Flow** and settings in **Blocking** mode.
+    ///
+    /// **The existing stream is closed** (discarding any pending buffers as if **Stream::abort**
+    /// had been called) and its callback dropped before the new one is opened, so audio is briefly
+    /// interrupted and the new stream is returned inactive (stopped). If reopening fails, both
+    /// streams are lost and the error is returned.
+    ///
+    /// Host API specific stream info attached to the **Parameters** is not carried over to the new
+    /// stream.
+    pub fn into_blocking(self) -> Result<Stream<Blocking<F::Buffer>, F>, Error>
+    where
+        F: Flow + Clone,
+    {
+        let (reopen, life) = self.close_for_reopen()?;
+        Stream::<Blocking<F::Buffer>, F>::open(life, reopen)
+    }
+
+    /// Move the stream to the device(s) described by the given `settings` while keeping the same
+    /// callback, along with any state captured by it.
+    ///
+    /// PortAudio cannot retarget an open stream, so this stops the stream (allowing pending buffers
+    /// to play out rather than cutting them off mid-buffer), closes it and then opens a new stream
+    /// with the given `settings` and the existing callback. If the stream was active it is
+    /// restarted on the new device. No audio is produced during the switch.
+    ///
+    /// Channel mutes (see **Stream::set_channel_mute**) are kept for the output channels that the
+    /// new stream still has.
+    ///
+    /// Returns the duration of the gap between stopping the old stream and starting the new one.
+    ///
+    /// If opening the new stream fails the error is returned and the stream is left closed.
+    pub fn switch_device<S>(&mut self, settings: S) -> Result<std::time::Duration, Error>
+    where
+        S: Settings<Flow = F>,
+        F: Flow,
+    {
+        let was_active = self.is_active()?;
+        let switch_start = std::time::Instant::now();
+        if was_active {
+            self.stop()?;
+        }
+        self.close()?;
+        self.pa_stream = ptr::null_mut();
+
+        let (flow, sample_rate, frames_per_buffer, flags) = settings.into_flow_and_settings();
+        let (in_params, out_params) = flow.params_both_directions();
+        self.mode.callback.in_channels = in_params.map(|p| p.channelCount).unwrap_or(0);
+        self.mode.callback.out_channels = out_params.map(|p| p.channelCount).unwrap_or(0);
+        // The callback is not running while the stream is closed, so the mutes may be replaced.
+        let mutes = (0..self.mode.callback.out_channels as usize).map(|channel| {
+            let mute = self.mode.output_mutes.get(channel);
+            AtomicBool::new(mute.map(|mute| mute.load(Ordering::Relaxed)) == Some(true))
+        });
+        self.mode.output_mutes = Arc::new(mutes.collect());
+        self.mode.callback.output_mutes = self.mode.output_mutes.clone();
+        self.mode.callback.in_format = in_params
+            .map(|p| p.sampleFormat.into())
+            .unwrap_or_else(SampleFormatFlags::empty);
+        self.mode.callback.out_format = out_params
+            .map(|p| p.sampleFormat.into())
+            .unwrap_or_else(SampleFormatFlags::empty);
+        self.flow = flow;
+        self.settings = OpenSettings {
+            sample_rate: sample_rate,
+            frames_per_buffer: frames_per_buffer,
+            flags: flags,
+        };
+        // Closing the old stream returned its slot.
+        self.acquire_slot()?;
+        self.pa_stream = match open_non_blocking_stream(
+            in_params,
+            out_params,
+            sample_rate,
+            frames_per_buffer,
+            flags,
+            &mut self.mode.callback,
+        ) {
+            Ok(pa_stream) => pa_stream,
+            Err(err) => {
+                self.release_slot();
+                return Err(err);
+            }
+        };
+
+        if was_active {
+            self.start()?;
+        }
+        Ok(switch_start.elapsed())
+    }
+
+    /// Retrieve CPU usage information for the specified stream.
+    ///
+    /// The "CPU Load" is a fraction of total CPU time consumed by a callback stream's audio
+    /// processing routines including, but not limited to the client supplied stream callback.
+    pub fn cpu_load(&self) -> f64 {
+        unsafe { ffi::Pa_GetStreamCpuLoad(self.pa_stream) }
+    }
+
+    /// Enable or disable metering of the input buffer passed to the callback.
+    ///
+    /// While enabled, the RMS and peak of every input block are measured before the callback is
+    /// called and may be retrieved via **Stream::input_level**. Metering is disabled by default so
+    /// that streams that don't need it don't pay for it.
+    pub fn set_input_metering(&self, enabled: bool) {
+        self.mode.state.meters.input.set_enabled(enabled);
+    }
+
+    /// Enable or disable metering of the output buffer written by the callback.
+    ///
+    /// While enabled, the RMS and peak of every output block are measured after the callback
+    /// returns and may be retrieved via **Stream::output_level**. Metering is disabled by default.
+    pub fn set_output_metering(&self, enabled: bool) {
+        self.mode.state.meters.output.set_enabled(enabled);
+    }
+
+    /// The **Level** of the most recent input block.
+    ///
+    /// Returns a silent **Level** if input metering is disabled, if the stream has no input or if
+    /// the input is non-interleaved.
+    pub fn input_level(&self) -> Level {
+        self.mode.state.meters.input.load()
+    }
+
+    /// The **Level** of the most recent output block.
+    ///
+    /// Returns a silent **Level** if output metering is disabled, if the stream has no output or if
+    /// the output is non-interleaved.
+    pub fn output_level(&self) -> Level {
+        self.mode.state.meters.output.load()
+    }
+
+    /// Pause the stream without stopping it.
+    ///
+    /// Unlike **Stream::stop**, the PortAudio stream remains running so that **Stream::resume**
+    /// takes effect almost immediately. The current callback buffer is faded out to avoid a click,
+    /// after which the user's callback is no longer called: the output is filled with silence and
+    /// any input is discarded.
+    ///
+    /// The stream's time continues to advance while paused.
+    pub fn pause(&self) {
+        self.mode.state.paused.store(true, Ordering::Release);
+    }
+
+    /// Resume a stream paused via **Stream::pause**.
+    ///
+    /// The user's callback is called again from the next callback buffer, which is faded in to
+    /// avoid a click.
+    pub fn resume(&self) {
+        self.mode.state.paused.store(false, Ordering::Release);
+    }
+
+    /// Whether or not the stream has been paused via **Stream::pause**.
+    pub fn is_paused(&self) -> bool {
+        self.mode.state.paused.load(Ordering::Acquire)
+    }
+
+    /// Enable de-click fades of the given length, or disable them with `None` (the default).
+    ///
+    /// While enabled, the output is faded in linearly whenever the stream is started via
+    /// **Stream::start**, and **Stream::stop** fades it out before stopping the stream, delaying
+    /// the stop by the length of the fade. A few milliseconds (e.g. 5ms) is usually enough to
+    /// avoid an audible click. **Stream::abort**, **Stream::request_stop** and dropping the stream
+    /// do not fade out.
+    pub fn set_declick(&self, fade: Option<std::time::Duration>) {
+        let seconds = fade.map_or(0.0, |fade| fade.as_secs_f64());
+        let frames = (seconds * self.settings.sample_rate).round() as u32;
+        self.mode
+            .state
+            .declick_frames
+            .store(frames, Ordering::Relaxed);
+    }
+
+    /// Register a `handler` that is called whenever a panic is caught in the stream's callback,
+    /// replacing any previously registered handler.
+    ///
+    /// A panic in the callback is never allowed to unwind into PortAudio. Instead, the output
+    /// buffer is silenced and **Abort** is returned to PortAudio, stopping the stream. The
+    /// `handler` is then called with the panic's message on a helper thread that is spawned when
+    /// the first handler is registered, never on the real-time audio thread, so it may block or
+    /// allocate freely, e.g. to update a metric or to flag the error to the user interface. A panic
+    /// caught before any handler is registered is reported once one is.
+    pub fn on_callback_panic<H>(&self, handler: H)
+    where
+        H: FnMut(&PanicPayload) + Send + 'static,
+    {
+        let mut slot = match self.mode.state.panic_handler.lock() {
+            Ok(slot) => slot,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *slot = Some(Box::new(handler));
+        drop(slot);
+        CallbackState::spawn_panic_reporter(&self.mode.state);
+    }
+
+    /// Mute or unmute the given output `channel`.
+    ///
+    /// The muted channel's samples are replaced with silence after the user's callback has filled
+    /// each output buffer, so the callback itself is unaffected. This may be called from any
+    /// thread that can access the stream and takes effect from the next callback buffer.
+    ///
+    /// Returns `Err(Error::InvalidChannelCount)` if `channel` is not less than the stream's output
+    /// channel count.
+    pub fn set_channel_mute(&self, channel: usize, muted: bool) -> Result<(), Error> {
+        match self.mode.output_mutes.get(channel) {
+            Some(mute) => {
+                mute.store(muted, Ordering::Relaxed);
+                Ok(())
+            }
+            None => Err(Error::InvalidChannelCount),
+        }
+    }
+
+    /// Whether or not each of the stream's output channels is muted via
+    /// **Stream::set_channel_mute**.
+    pub fn channel_mutes(&self) -> Vec<bool> {
+        let mutes = self.mode.output_mutes.iter();
+        mutes.map(|mute| mute.load(Ordering::Relaxed)).collect()
+    }
+
+    /// Request that the stream finish gracefully, as though the callback had returned
+    /// **Complete**.
+    ///
+    /// This may be called from any thread that can access the stream. The callback is still called
+    /// on its next invocation, after which **Complete** is returned to PortAudio in place of
+    /// **Continue**. The buffers already produced are played out and the stream then becomes
+    /// inactive (see **Stream::is_active**).
+    ///
+    /// The request is consumed by the callback invocation that completes the stream, and any
+    /// request still pending is cleared by **Stream::start**, so a restarted stream runs as
+    /// normal.
+    pub fn request_stop(&self) {
+        self.mode
+            .state
+            .stop_requested
+            .store(true, Ordering::Release);
+    }
+
+    /// The number of times that the callback has been called since the stream was opened.
+    pub fn callback_count(&self) -> u64 {
+        self.mode.state.counters.callbacks.load(Ordering::Relaxed)
+    }
+
+    /// The number of buffer underflows and overflows reported to the callback since the stream
+    /// was opened.
+    pub fn xruns(&self) -> Xruns {
+        self.mode.state.counters.xruns()
+    }
+
+    /// Statistics on the number of frames passed to the callback since the stream was opened.
+    ///
+    /// Reveals whether the host API delivers consistent or varying block sizes, which is
+    /// particularly useful for streams opened with `FRAMES_PER_BUFFER_UNSPECIFIED`.
+    pub fn buffer_stats(&self) -> BufferStats {
+        self.mode.state.counters.frames.stats()
+    }
+
+    /// Gather a summary of the stream's current state for monitoring purposes.
+    ///
+    /// The counters are read without locking, so a **Snapshot** taken while the stream is running
+    /// may be one callback out of date with respect to the other fields.
+    pub fn snapshot(&self) -> Result<Snapshot, Error> {
+        Ok(Snapshot {
+            is_active: self.is_active()?,
+            is_stopped: self.is_stopped()?,
+            cpu_load: self.cpu_load(),
+            time: self.time(),
+            info: self.info(),
+            callback_count: self.callback_count(),
+            xruns: self.xruns(),
+            buffer_stats: self.buffer_stats(),
+        })
+    }
+}
+
+impl<M, F> Drop for Stream<M, F> {
+    fn drop(&mut self) {
+        self.stop_immediately().ok();
+        self.close().ok();
+        self.release_slot();
+    }
+}
+
+/// A callback procedure to be used by portaudio in the case that a user_callback has been given
 /// upon opening the stream (`Stream::open`).
 extern "C" fn stream_callback_proc(
     input: *const raw::c_void,
@@ -1438,6 +3861,488 @@ extern "C" fn stream_callback_proc(
     flags: ffi::PaStreamCallbackFlags,
     user_callback_ptr: *mut raw::c_void,
 ) -> ffi::PaStreamCallbackResult {
-    let callback = user_callback_ptr as *mut CallbackFnWrapper;
-    unsafe { ((*callback).f)(input, output, frame_count, time_info, flags) }
+    let callback = unsafe { &mut *(user_callback_ptr as *mut CallbackFnWrapper) };
+    let (in_channels, out_channels) = (callback.in_channels, callback.out_channels);
+    let frames = frame_count as usize;
+    callback
+        .state
+        .counters
+        .record(CallbackFlags::from_bits_truncate(flags));
+    callback.state.counters.frames.record(frames as u64);
+    if !input.is_null() {
+        callback.frame_totals.add_read(frames as u64);
+    }
+    if !output.is_null() {
+        callback.frame_totals.add_written(frames as u64);
+    }
+    callback
+        .state
+        .meters
+        .input
+        .measure(input, callback.in_format, frames * in_channels as usize);
+    let pause_requested = callback.state.paused.load(Ordering::Acquire);
+    let result = if callback.is_paused && pause_requested {
+        // While paused the user's callback is skipped, discarding any input.
+        if !output.is_null() {
+            unsafe { write_silence(output, callback.out_format, frames, out_channels as usize) };
+        }
+        ffi::PA_CONTINUE
+    } else {
+        // Unwinding into PortAudio is undefined behaviour, so a panic is caught here and the stream
+        // is aborted instead.
+        let f = &mut callback.f;
+        let call = || {
+            f(
+                input,
+                output,
+                frame_count,
+                time_info,
+                flags,
+                in_channels,
+                out_channels,
+            )
+        };
+        let result = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(call)) {
+            Ok(result) => result,
+            Err(payload) => {
+                if !output.is_null() {
+                    let (format, channels) = (callback.out_format, out_channels as usize);
+                    unsafe { write_silence(output, format, frames, channels) };
+                }
+                callback.state.stash_panic(payload);
+                return ffi::PA_ABORT;
+            }
+        };
+        // Fade the block in which the stream is paused or resumed to avoid a click.
+        if callback.is_paused != pause_requested {
+            let (from, to) = if pause_requested {
+                (1.0, 0.0)
+            } else {
+                (0.0, 1.0)
+            };
+            if !output.is_null() {
+                let (format, channels) = (callback.out_format, out_channels as usize);
+                unsafe { apply_ramp(output, format, frames, channels, from, to) };
+            }
+            callback.is_paused = pause_requested;
+        }
+        result
+    };
+    if !output.is_null() {
+        for (channel, muted) in callback.output_mutes.iter().enumerate() {
+            if muted.load(Ordering::Relaxed) {
+                let (format, channels) = (callback.out_format, out_channels as usize);
+                unsafe { write_channel_silence(output, format, frames, channels, channel) };
+            }
+        }
+    }
+    if !output.is_null() {
+        unsafe { declick(callback, output, frames) };
+    }
+    // Complete rather than continue if a stop was requested so that the stream drains cleanly. The
+    // request is consumed so that it does not affect the stream once restarted.
+    let stop_requested = callback.state.stop_requested.swap(false, Ordering::AcqRel);
+    let result = if stop_requested && result == ffi::PA_CONTINUE {
+        ffi::PA_COMPLETE
+    } else {
+        result
+    };
+    callback.state.meters.output.measure(
+        output as *const raw::c_void,
+        callback.out_format,
+        frames * out_channels as usize,
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_len_rejects_overflow() {
+        assert_eq!(samples_len(256, 2), Ok(512));
+        assert_eq!(samples_len(256, -1), Err(Error::InvalidChannelCount));
+        if ::std::mem::size_of::<usize>() == 4 {
+            assert_eq!(samples_len(u32::MAX, 2), Err(Error::BufferTooBig));
+        }
+    }
+
+    #[test]
+    fn block_level_normalises_samples() {
+        let samples: [i16; 4] = [16_384, -16_384, 16_384, -32_768];
+        let ptr = samples.as_ptr() as *const raw::c_void;
+        let level = unsafe { block_level(ptr, sample_format_flags::INT_16, 4) }.unwrap();
+        assert_eq!(level.peak, 1.0);
+        assert!((level.rms - (1.75f32 / 4.0).sqrt()).abs() < 1e-6);
+
+        let samples: [f32; 2] = [0.5, -0.5];
+        let ptr = samples.as_ptr() as *const raw::c_void;
+        let level = unsafe { block_level(ptr, sample_format_flags::FLOAT_32, 2) }.unwrap();
+        assert_eq!(
+            level,
+            Level {
+                rms: 0.5,
+                peak: 0.5
+            }
+        );
+
+        let format = sample_format_flags::FLOAT_32 | sample_format_flags::NON_INTERLEAVED;
+        assert_eq!(unsafe { block_level(ptr, format, 2) }, None);
+    }
+
+    #[test]
+    fn disabled_meter_reads_silent() {
+        let samples: [f32; 2] = [0.5, -0.5];
+        let ptr = samples.as_ptr() as *const raw::c_void;
+        let meter = LevelMeter::default();
+        meter.set_enabled(true);
+        meter.measure(ptr, sample_format_flags::FLOAT_32, 2);
+        assert_eq!(meter.load().peak, 0.5);
+
+        // A block measured as the meter is disabled may store its level after the reset.
+        meter.set_enabled(false);
+        meter.store(Level {
+            rms: 0.5,
+            peak: 0.5,
+        });
+        assert_eq!(meter.load(), Level::default());
+        meter.set_enabled(true);
+        assert_eq!(meter.load(), Level::default());
+    }
+
+    #[test]
+    fn pause_ramp_and_silence() {
+        let mut samples = [1.0f32; 8];
+        let ptr = samples.as_mut_ptr() as *mut raw::c_void;
+        unsafe { apply_ramp(ptr, sample_format_flags::FLOAT_32, 4, 2, 1.0, 0.0) };
+        assert_eq!(samples, [1.0, 1.0, 0.75, 0.75, 0.5, 0.5, 0.25, 0.25]);
+
+        let mut samples = [0u8; 4];
+        let ptr = samples.as_mut_ptr() as *mut raw::c_void;
+        unsafe { write_silence(ptr, sample_format_flags::UINT_8, 2, 2) };
+        assert_eq!(samples, [128; 4]);
+
+        let mut samples = [[0x00u8, 0x00, 0x40], [0x00, 0x00, 0xc0]];
+        let ptr = samples.as_mut_ptr() as *mut raw::c_void;
+        unsafe { apply_ramp(ptr, sample_format_flags::INT_24, 1, 2, 0.5, 0.5) };
+        assert_eq!(samples, [[0x00, 0x00, 0x20], [0x00, 0x00, 0xe0]]);
+    }
+
+    #[test]
+    fn fade_spans_callbacks() {
+        let mut fade = Fade::new(1.0, 0.0, 4);
+        let mut samples = [1.0f32; 3];
+        let ptr = samples.as_mut_ptr() as *mut raw::c_void;
+        assert!(!unsafe { fade.apply(ptr, sample_format_flags::FLOAT_32, 3, 1) });
+        assert_eq!(samples, [1.0, 0.75, 0.5]);
+
+        let mut samples = [1.0f32; 3];
+        let ptr = samples.as_mut_ptr() as *mut raw::c_void;
+        assert!(unsafe { fade.apply(ptr, sample_format_flags::FLOAT_32, 3, 1) });
+        assert_eq!(samples, [0.25, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn callback_panic_is_caught_and_reported() {
+        let state = Arc::new(CallbackState::default());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        *state.panic_handler.lock().unwrap() = Some(Box::new(move |payload: &PanicPayload| {
+            sender.send(payload.clone()).unwrap();
+        }));
+        CallbackState::spawn_panic_reporter(&state);
+        let mut wrapper = CallbackFnWrapper {
+            f: Box::new(|_, _, _, _, _, _, _| panic!("callback failed")),
+            in_channels: 0,
+            out_channels: 1,
+            in_format: sample_format_flags::FLOAT_32,
+            out_format: sample_format_flags::FLOAT_32,
+            state: state.clone(),
+            frame_totals: Arc::new(FrameTotals::default()),
+            output_mutes: Arc::new(vec![AtomicBool::new(false)]),
+            is_paused: false,
+            fade: None,
+        };
+        let mut samples = [1.0f32; 4];
+        let output = samples.as_mut_ptr() as *mut raw::c_void;
+        let user = &mut wrapper as *mut CallbackFnWrapper as *mut raw::c_void;
+        let result = stream_callback_proc(ptr::null(), output, 4, ptr::null(), 0, user);
+        assert_eq!(result, ffi::PA_ABORT);
+        assert_eq!(samples, [0.0; 4]);
+
+        let timeout = std::time::Duration::from_secs(5);
+        let payload = receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(payload.message(), "callback failed");
+        state.close();
+    }
+
+    #[test]
+    fn second_panic_keeps_the_first_payload() {
+        let state = CallbackState::default();
+        state.stash_panic(Box::new("first"));
+        state.stash_panic(Box::new("second"));
+        let payload = state.panic_payload.lock().unwrap().take().unwrap();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"first"));
+    }
+
+    #[test]
+    fn reopened_flow_drops_host_api_specific_stream_info() {
+        let mut info = 0u32;
+        let params = Parameters::<f32>::new(DeviceIndex(0), 2, true, 0.1);
+        let params =
+            unsafe { params.with_host_api_specific_stream_info(&mut info as *mut u32 as _) };
+        let flow = Output { params: params }.without_host_api_specific_stream_info();
+        let (_, out_params) = flow.params_both_directions();
+        assert!(out_params.unwrap().hostApiSpecificStreamInfo.is_null());
+    }
+
+    #[test]
+    fn callback_counts_frames_per_direction() {
+        let frame_totals = Arc::new(FrameTotals::default());
+        let mut wrapper = CallbackFnWrapper {
+            f: Box::new(|_, _, _, _, _, _, _| ffi::PA_CONTINUE),
+            in_channels: 0,
+            out_channels: 1,
+            in_format: sample_format_flags::FLOAT_32,
+            out_format: sample_format_flags::FLOAT_32,
+            state: Arc::new(CallbackState::default()),
+            frame_totals: frame_totals.clone(),
+            output_mutes: Arc::new(vec![AtomicBool::new(false)]),
+            is_paused: false,
+            fade: None,
+        };
+        let mut samples = [0.0f32; 4];
+        let output = samples.as_mut_ptr() as *mut raw::c_void;
+        let user = &mut wrapper as *mut CallbackFnWrapper as *mut raw::c_void;
+        stream_callback_proc(ptr::null(), output, 4, ptr::null(), 0, user);
+        stream_callback_proc(ptr::null(), output, 3, ptr::null(), 0, user);
+        assert_eq!(frame_totals.written(), 7);
+        assert_eq!(frame_totals.read(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn wake_timer_wakes_after_deadline() {
+        struct Flag(std::sync::mpsc::Sender<std::time::Instant>);
+        impl std::task::Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                let _ = self.0.send(std::time::Instant::now());
+            }
+        }
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let waker = std::task::Waker::from(Arc::new(Flag(sender)));
+        let timer = WakeTimer::spawn().unwrap();
+        let at = std::time::Instant::now() + std::time::Duration::from_millis(20);
+        timer.wake_at(waker, at);
+        let woken = receiver.recv_timeout(std::time::Duration::from_secs(5));
+        assert!(woken.unwrap() >= at);
+        drop(timer);
+    }
+
+    #[test]
+    fn split_halves_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<StreamReader<Input<f32>>>();
+        assert_send_sync::<StreamController<Input<f32>>>();
+        assert_send_sync::<StreamReader<Duplex<i16, f32>>>();
+    }
+
+    #[test]
+    fn start_and_stop_need_no_mode_bound() {
+        fn restart<M, F>(stream: &mut Stream<M, F>) -> Result<(), Error> {
+            stream.stop()?;
+            stream.start()
+        }
+        let _ = restart::<Blocking<Buffer>, Input<f32>>;
+    }
+
+    #[test]
+    fn info_fields_follow_struct_version() {
+        assert_eq!(unsafe { Info::from_ptr(ptr::null()) }, Info::default());
+        let mut c_info = ffi::PaStreamInfo {
+            structVersion: 1,
+            inputLatency: 0.01,
+            outputLatency: 0.02,
+            sampleRate: 48_000.0,
+        };
+        let info = unsafe { Info::from_ptr(&c_info) };
+        assert_eq!(info.struct_version(), 1);
+        assert_eq!(info, Info::from(c_info));
+
+        c_info.structVersion = 0;
+        let info = unsafe { Info::from_ptr(&c_info) };
+        assert_eq!(info.sample_rate, 0.0);
+    }
+
+    #[test]
+    fn zero_channel_directions_are_omitted() {
+        let params = |channels| Parameters::<f32>::new(DeviceIndex(0), channels, true, 0.1);
+        let duplex = |ins, outs| {
+            let flow = Duplex {
+                in_params: params(ins),
+                out_params: params(outs),
+            };
+            let (in_params, out_params) = flow.params_both_directions();
+            let channels = |p: Option<ffi::PaStreamParameters>| p.map(|p| p.channelCount);
+            let valid = check_directions(&in_params, &out_params).is_ok();
+            (channels(in_params), channels(out_params), valid)
+        };
+        assert_eq!(duplex(1, 2), (Some(1), Some(2), true));
+        assert_eq!(duplex(0, 2), (None, Some(2), true));
+        assert_eq!(duplex(1, 0), (Some(1), None, true));
+        assert_eq!(duplex(0, 0), (None, None, false));
+
+        let input = Input { params: params(0) }.params_both_directions();
+        assert_eq!(
+            check_directions(&input.0, &input.1),
+            Err(Error::InvalidChannelCount)
+        );
+        let output = Output { params: params(0) }.params_both_directions();
+        assert_eq!(
+            check_directions(&output.0, &output.1),
+            Err(Error::InvalidChannelCount)
+        );
+    }
+
+    #[test]
+    fn duplex_callback_args_accept_a_missing_side() {
+        let time_info = ffi::PaStreamCallbackTimeInfo {
+            inputBufferAdcTime: 0.0,
+            currentTime: 0.0,
+            outputBufferDacTime: 0.0,
+        };
+        let mut output = [0.0f32; 8];
+        let out_ptr = output.as_mut_ptr() as *mut raw::c_void;
+        let args = <Duplex<f32, f32> as Flow>::new_callback_args(
+            ptr::null(),
+            out_ptr,
+            4,
+            &time_info,
+            0,
+            0,
+            2,
+        );
+        assert_eq!((args.in_buffer.len(), args.out_buffer.len()), (0, 8));
+    }
+
+    #[test]
+    fn callback_time_info_passes_the_adc_time_through() {
+        let time_info = ffi::PaStreamCallbackTimeInfo {
+            inputBufferAdcTime: 2.0,
+            currentTime: 3.0,
+            outputBufferDacTime: 4.0,
+        };
+        let input = [0.0f32; 4];
+        let in_ptr = input.as_ptr() as *const raw::c_void;
+        let args = <Input<f32> as Flow>::new_callback_args(
+            in_ptr,
+            ptr::null_mut(),
+            4,
+            &time_info,
+            0,
+            1,
+            0,
+        );
+        assert_eq!(args.time.buffer_adc, 2.0);
+        assert_eq!(args.time.frame_adc(0, 4.0), 2.0);
+        assert_eq!(args.time.frame_adc(2, 4.0), 2.5);
+
+        let mut output = [0.0f32; 4];
+        let out_ptr = output.as_mut_ptr() as *mut raw::c_void;
+        let args =
+            <Duplex<f32, f32> as Flow>::new_callback_args(in_ptr, out_ptr, 4, &time_info, 0, 1, 1);
+        assert_eq!(args.time.in_buffer_adc, 2.0);
+        assert_eq!(args.time.out_buffer_dac, 4.0);
+        assert_eq!(args.time.in_frame_adc(3, 4.0), 2.75);
+    }
+
+    #[test]
+    fn channel_silence() {
+        let mut samples = [1i16, 2, 3, 4, 5, 6];
+        let ptr = samples.as_mut_ptr() as *mut raw::c_void;
+        unsafe { write_channel_silence(ptr, sample_format_flags::INT_16, 2, 3, 1) };
+        assert_eq!(samples, [1, 0, 3, 4, 0, 6]);
+
+        let (mut left, mut right) = ([1u8, 2], [3u8, 4]);
+        let mut buffers = [left.as_mut_ptr(), right.as_mut_ptr()];
+        let ptr = buffers.as_mut_ptr() as *mut raw::c_void;
+        let format = sample_format_flags::UINT_8 | sample_format_flags::NON_INTERLEAVED;
+        unsafe { write_channel_silence(ptr, format, 2, 2, 1) };
+        assert_eq!((left, right), ([1, 2], [128, 128]));
+    }
+
+    #[test]
+    fn wait_until_available_times_out() {
+        let timeout = std::time::Duration::from_millis(5);
+        let mut polls = 0;
+        let result = wait_until_available(64, timeout, || {
+            polls += 1;
+            Ok(Available::Frames(32))
+        });
+        assert_eq!(result, Err(Error::TimedOut));
+        assert!(polls > 1);
+
+        let mut frames = 0;
+        let result = wait_until_available(64, timeout * 100, || {
+            frames += 16;
+            Ok(Available::Frames(frames))
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(frames, 64);
+    }
+
+    #[test]
+    fn wait_until_drained_returns_promptly() {
+        // A 256 frame buffer that drains a 64 frame period per poll.
+        let timeout = std::time::Duration::from_secs(10);
+        let start = std::time::Instant::now();
+        let mut free = 0;
+        let drained = wait_until_drained(256, timeout, || {
+            free = std::cmp::min(free + 64, 256);
+            Ok(Available::Frames(free))
+        });
+        assert_eq!(drained, Ok(true));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        let timeout = std::time::Duration::from_millis(5);
+        let drained = wait_until_drained(256, timeout, || Ok(Available::Frames(192)));
+        assert_eq!(drained, Ok(false));
+
+        let drained = wait_until_drained(256, timeout, || Err(Error::BadStreamPtr));
+        assert_eq!(drained, Err(Error::BadStreamPtr));
+    }
+
+    #[test]
+    fn counters_record_xruns() {
+        let counters = Counters::default();
+        counters.record(CallbackFlags::empty());
+        counters.record(callback_flags::INPUT_OVERFLOW | callback_flags::OUTPUT_UNDERFLOW);
+        counters.record(callback_flags::OUTPUT_UNDERFLOW | callback_flags::PRIMING_OUTPUT);
+        assert_eq!(counters.callbacks.load(Ordering::Relaxed), 3);
+        let xruns = counters.xruns();
+        assert_eq!(xruns.input_overflows, 1);
+        assert_eq!(xruns.output_underflows, 2);
+        assert_eq!(xruns.total(), 3);
+    }
+
+    #[test]
+    fn frame_counts_stats() {
+        let frames = FrameCounts::default();
+        assert_eq!(frames.stats(), BufferStats::default());
+        for &n in &[256, 64, 512, 192] {
+            frames.record(n);
+        }
+        let stats = frames.stats();
+        assert_eq!((stats.min_frames, stats.max_frames), (64, 512));
+        assert_eq!(stats.mean_frames, 256.0);
+        assert_eq!(stats.callbacks, 4);
+    }
+
+    #[test]
+    fn buffer_new_rejects_extreme_sizes() {
+        assert!(Buffer::new::<f32>(u32::MAX, i32::MAX).err() == Some(Error::BufferTooBig));
+        assert!(Buffer::new::<f32>(u32::MAX, -1).err() == Some(Error::InvalidChannelCount));
+        assert!(Buffer::new::<f32>(256, 2).is_ok());
+    }
 }
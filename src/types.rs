@@ -25,6 +25,8 @@
 
 use ffi;
 use num::FromPrimitive;
+use std::borrow::Cow;
+use std::ffi::{CStr, CString};
 use std::os::raw;
 
 pub use self::sample_format_flags::SampleFormatFlags;
@@ -33,8 +35,18 @@ pub use self::sample_format_flags::SampleFormatFlags;
 ///
 /// Values of this type usually range from 0 to (PortAudio::device_count-1).
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct DeviceIndex(pub u32);
 
+/// An identifier for a device that, unlike a **DeviceIndex**, is reasonably stable across runs and
+/// hotplug events. See **DeviceInfo::stable_id**.
+///
+/// The inner value may be persisted (e.g. within a config file) and later used to find the device
+/// via **PortAudio::find_device_by_stable_id**.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StableDeviceId(pub u64);
+
 /// The device to be used by some stream.
 ///
 /// This is used as a field within the Settings for a **Stream**.
@@ -248,6 +260,7 @@ enum_from_primitive! {
 /// Unchanging unique identifiers for each supported host API
 #[repr(u32)]
 #[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum HostApiTypeId {
     /// In development host
     InDevelopment = ffi::PaHostApiTypeId_paInDevelopment,
@@ -280,6 +293,109 @@ pub enum HostApiTypeId {
 }
 }
 
+/// A rough classification of the latency typically achievable with a host API.
+///
+/// Variants are ordered from lowest to highest latency.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LatencyClass {
+    /// Professional or low-level APIs designed for low latency audio, e.g. ASIO, JACK, CoreAudio,
+    /// WASAPI, WDM-KS and ALSA.
+    Low,
+    /// General purpose APIs that add some buffering, e.g. DirectSound and OSS.
+    Medium,
+    /// Legacy APIs with considerable internal buffering, e.g. MME.
+    High,
+}
+
+impl HostApiTypeId {
+    /// A rough classification of the latency typically achievable with the host API.
+    ///
+    /// This is a heuristic based on the design of each API - actual latency depends heavily on the
+    /// drivers and hardware in use.
+    pub fn latency_class(&self) -> LatencyClass {
+        match *self {
+            HostApiTypeId::ASIO
+            | HostApiTypeId::JACK
+            | HostApiTypeId::CoreAudio
+            | HostApiTypeId::WASAPI
+            | HostApiTypeId::WDMKS
+            | HostApiTypeId::ALSA
+            | HostApiTypeId::AudioScienceHPI => LatencyClass::Low,
+            HostApiTypeId::DirectSound | HostApiTypeId::OSS => LatencyClass::Medium,
+            HostApiTypeId::MME
+            | HostApiTypeId::SoundManager
+            | HostApiTypeId::AL
+            | HostApiTypeId::BeOS
+            | HostApiTypeId::InDevelopment => LatencyClass::High,
+        }
+    }
+
+    /// The sample formats that the host API's devices are most likely to handle natively (and in
+    /// turn without conversion), ordered from most to least preferred.
+    ///
+    /// This is a heuristic - e.g. ASIO drivers choose their own format, and the hardware behind
+    /// ALSA and OSS devices may support any integer format.
+    pub fn preferred_sample_formats(&self) -> &'static [SampleFormat] {
+        const FLOAT: &[SampleFormat] = &[SampleFormat::F32, SampleFormat::I32, SampleFormat::I16];
+        const WIDE_INT: &[SampleFormat] =
+            &[SampleFormat::I32, SampleFormat::I16, SampleFormat::F32];
+        const NARROW_INT: &[SampleFormat] =
+            &[SampleFormat::I16, SampleFormat::I32, SampleFormat::F32];
+        match *self {
+            HostApiTypeId::CoreAudio
+            | HostApiTypeId::JACK
+            | HostApiTypeId::WASAPI
+            | HostApiTypeId::InDevelopment => FLOAT,
+            HostApiTypeId::ASIO
+            | HostApiTypeId::ALSA
+            | HostApiTypeId::WDMKS
+            | HostApiTypeId::AudioScienceHPI => WIDE_INT,
+            HostApiTypeId::DirectSound
+            | HostApiTypeId::MME
+            | HostApiTypeId::OSS
+            | HostApiTypeId::SoundManager
+            | HostApiTypeId::AL
+            | HostApiTypeId::BeOS => NARROW_INT,
+        }
+    }
+}
+
+/// The direction in which audio flows through a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Audio received from the device's ADC.
+    Input,
+    /// Audio sent to the device's DAC.
+    Output,
+}
+
+/// The purpose for which a default device is used.
+///
+/// Windows keeps a separate default device for each role, e.g. so that calls use a headset while
+/// music plays through the speakers. Other platforms have a single default device for all roles.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// Voice communication, e.g. calls and chat.
+    Communications,
+    /// Music, movies and other media playback or recording.
+    Multimedia,
+}
+
+/// The configuration given to PortAudio's ALSA host API via **PortAudio::new_with_alsa_config**.
+///
+/// The configuration replaces ALSA's top-level configuration file (usually
+/// `/usr/share/alsa/alsa.conf`), so it must define every PCM and control device that is used.
+#[cfg(target_os = "linux")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AlsaConfig<'a> {
+    /// The path to an existing configuration file.
+    Path(&'a std::path::Path),
+    /// The contents of a configuration file, e.g.
+    /// `pcm.!default { type pulse } ctl.!default { type pulse }` to route the default devices to a
+    /// PulseAudio or PipeWire server.
+    Inline(&'a str),
+}
+
 /// A structure containing information about a particular host API.
 #[derive(Clone, Debug, PartialEq)]
 pub struct HostApiInfo<'a> {
@@ -396,7 +512,11 @@ pub struct DeviceInfo<'a> {
     /// The version of the struct
     pub struct_version: i32,
     /// The name of the device
-    pub name: &'a str,
+    ///
+    /// Some drivers report names that are not valid UTF-8 (e.g. MME on Windows, which uses the
+    /// system's locale-specific code page). Any invalid sequences within such names are replaced
+    /// with `U+FFFD REPLACEMENT CHARACTER`, in which case the name is owned.
+    pub name: Cow<'a, str>,
     /// Host API identifier
     pub host_api: HostApiIndex,
     /// Maximal number of input channels for this device
@@ -416,11 +536,61 @@ pub struct DeviceInfo<'a> {
 }
 
 impl<'a> DeviceInfo<'a> {
+    /// Produce an identifier for the device that is reasonably stable across runs.
+    ///
+    /// The identifier is a hash of the device's host API type, name and maximum input and output
+    /// channel counts. It uses a fixed hash function (64-bit FNV-1a), so the same device produces
+    /// the same identifier regardless of the Rust version or the platform's device ordering.
+    ///
+    /// PortAudio provides no true persistent device identifiers, so this is a heuristic:
+    ///
+    /// - Two identical devices (e.g. two of the same USB interface) share the same identifier.
+    /// - The identifier changes if the driver or OS renames the device or changes its channel
+    ///   counts.
+    /// - The identifier also depends on the host API that the device is accessed through, so the
+    ///   same physical device has a different identifier under e.g. MME and WASAPI.
+    ///
+    /// The `host_type` must be that of the device's `host_api`. Use
+    /// **PortAudio::device_stable_id** to look it up automatically.
+    pub fn stable_id(&self, host_type: HostApiTypeId) -> StableDeviceId {
+        self.stable_id_with_host_type(host_type as i64)
+    }
+
+    /// The **DeviceInfo::stable_id** for the given raw host API type, or `-1` if the host API is
+    /// unknown.
+    pub(crate) fn stable_id_with_host_type(&self, host_type: i64) -> StableDeviceId {
+        let mut hash = Fnv1a::new();
+        hash.write(&host_type.to_le_bytes());
+        hash.write(&(self.name.len() as u64).to_le_bytes());
+        hash.write(self.name.as_bytes());
+        hash.write(&self.max_input_channels.to_le_bytes());
+        hash.write(&self.max_output_channels.to_le_bytes());
+        StableDeviceId(hash.finish())
+    }
+
+    /// The USB vendor and product IDs of the device, if they can be discovered.
+    ///
+    /// PortAudio does not expose USB IDs, so this is best-effort and **only implemented on Linux**.
+    /// There, ALSA device names include the card number (e.g. `USB Audio: - (hw:1,0)`), for which
+    /// the IDs are read from `/proc/asound/card<N>/usbid`.
+    ///
+    /// Returns `None` in all other cases: on other platforms, for devices of other host APIs (e.g.
+    /// JACK or PulseAudio's ALSA plugin) and for non-USB devices. Core Audio and WASAPI device
+    /// names do not contain the IDs, which would have to be read from IOKit or the device's
+    /// `PKEY_Device_InstanceId` property instead.
+    ///
+    /// Unlike the name, the IDs do not change when the OS renames a device, so they may be used
+    /// alongside **DeviceInfo::stable_id** to match a physical device. They are not included in
+    /// the stable identifier itself, as they are not available on all platforms.
+    pub fn usb_ids(&self) -> Option<(u16, u16)> {
+        alsa_card_usb_ids(&self.name)
+    }
+
     /// Construct a **DeviceInfo** from the equivalent C struct.
     pub fn from_c_info(c_info: ffi::PaDeviceInfo) -> DeviceInfo<'a> {
         DeviceInfo {
             struct_version: c_info.structVersion,
-            name: ffi::c_str_to_str(c_info.name).unwrap_or("<Failed to convert str from CStr>"),
+            name: device_name(c_info.name),
             host_api: c_info.hostApi,
             max_input_channels: c_info.maxInputChannels,
             max_output_channels: c_info.maxOutputChannels,
@@ -433,11 +603,92 @@ impl<'a> DeviceInfo<'a> {
     }
 }
 
+/// The card number within an ALSA device name such as `HDA Intel PCH: ALC892 Analog (hw:0,0)`.
+#[cfg(any(target_os = "linux", test))]
+fn alsa_card_number(name: &str) -> Option<u32> {
+    let start = name.rfind("(hw:")? + "(hw:".len();
+    let digits: String = name[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Parse the contents of an ALSA `usbid` file, e.g. `046d:0a44`.
+#[cfg(any(target_os = "linux", test))]
+fn parse_alsa_usbid(usbid: &str) -> Option<(u16, u16)> {
+    let mut ids = usbid.trim().splitn(2, ':');
+    let vendor = u16::from_str_radix(ids.next()?, 16).ok()?;
+    let product = u16::from_str_radix(ids.next()?, 16).ok()?;
+    Some((vendor, product))
+}
+
+#[cfg(target_os = "linux")]
+fn alsa_card_usb_ids(name: &str) -> Option<(u16, u16)> {
+    let card = alsa_card_number(name)?;
+    let path = format!("/proc/asound/card{}/usbid", card);
+    let usbid = ::std::fs::read_to_string(path).ok()?;
+    parse_alsa_usbid(&usbid)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn alsa_card_usb_ids(_name: &str) -> Option<(u16, u16)> {
+    None
+}
+
+/// The 64-bit FNV-1a hash function.
+///
+/// Used for **StableDeviceId**s rather than the std `DefaultHasher`, whose output may change
+/// between Rust versions.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Convert the given device name to a `str`, replacing any invalid UTF-8 sequences.
+fn device_name<'a>(c_name: *const raw::c_char) -> Cow<'a, str> {
+    if c_name.is_null() {
+        return Cow::Borrowed("");
+    }
+    unsafe { CStr::from_ptr(c_name) }.to_string_lossy()
+}
+
+/// The C string for the given device name.
+///
+/// A borrowed name is PortAudio's own nul-terminated name, so it is pointed to directly. A name
+/// that had to be converted lossily has no nul-terminated copy, so one is allocated and leaked, as
+/// a **PaDeviceInfo** has no owner to free it.
+fn device_c_name(name: Cow<str>) -> *const raw::c_char {
+    match name {
+        Cow::Borrowed("") => b"\0".as_ptr() as *const raw::c_char,
+        Cow::Borrowed(name) => ffi::str_to_c_str(name),
+        // The name was converted from a C string, so it contains no nul.
+        Cow::Owned(name) => match CString::new(name) {
+            Ok(name) => name.into_raw(),
+            Err(_) => b"\0".as_ptr() as *const raw::c_char,
+        },
+    }
+}
+
 impl<'a> From<DeviceInfo<'a>> for ffi::PaDeviceInfo {
     fn from(info: DeviceInfo<'a>) -> Self {
         ffi::PaDeviceInfo {
             structVersion: info.struct_version as raw::c_int,
-            name: ffi::str_to_c_str(info.name),
+            name: device_c_name(info.name),
             hostApi: info.host_api as ffi::PaHostApiIndex,
             maxInputChannels: info.max_input_channels as raw::c_int,
             maxOutputChannels: info.max_output_channels as raw::c_int,
@@ -449,3 +700,80 @@ impl<'a> From<DeviceInfo<'a>> for ffi::PaDeviceInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_reference_values() {
+        let hash = |bytes: &[u8]| {
+            let mut hash = Fnv1a::new();
+            hash.write(bytes);
+            hash.finish()
+        };
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn invalid_utf8_device_names_are_replaced() {
+        let c_info = |name: &[u8]| ffi::PaDeviceInfo {
+            structVersion: 2,
+            name: name.as_ptr() as *const raw::c_char,
+            hostApi: 0,
+            maxInputChannels: 0,
+            maxOutputChannels: 2,
+            defaultLowInputLatency: 0.0,
+            defaultLowOutputLatency: 0.01,
+            defaultHighInputLatency: 0.0,
+            defaultHighOutputLatency: 0.1,
+            defaultSampleRate: 44_100.0,
+        };
+        // "Lautsprecher (Gerät)" encoded as Windows-1252 rather than UTF-8.
+        let name = b"Lautsprecher (Ger\xe4t)\0";
+        let info = DeviceInfo::from_c_info(c_info(name));
+        assert_eq!(info.name, "Lautsprecher (Ger\u{fffd}t)");
+        // Converting back yields a nul-terminated copy of the converted name.
+        let c_name = ffi::PaDeviceInfo::from(info).name;
+        let converted = unsafe { CStr::from_ptr(c_name) };
+        assert_eq!(converted.to_str(), Ok("Lautsprecher (Ger\u{fffd}t)"));
+        drop(unsafe { CString::from_raw(c_name as *mut raw::c_char) });
+
+        let name = "Lautsprecher (Gerät)\0".as_bytes();
+        let info = DeviceInfo::from_c_info(c_info(name));
+        assert_eq!(info.name, "Lautsprecher (Gerät)");
+        assert!(matches!(info.name, Cow::Borrowed(_)));
+        // A valid name converts back to PortAudio's original name rather than a copy.
+        let info = ffi::PaDeviceInfo::from(info);
+        assert_eq!(info.name, name.as_ptr() as *const raw::c_char);
+    }
+
+    #[test]
+    fn stable_id_depends_on_the_given_host_type() {
+        let info = DeviceInfo::from_c_info(ffi::PaDeviceInfo {
+            structVersion: 2,
+            name: b"Speakers\0".as_ptr() as *const raw::c_char,
+            hostApi: 0,
+            maxInputChannels: 0,
+            maxOutputChannels: 2,
+            defaultLowInputLatency: 0.0,
+            defaultLowOutputLatency: 0.01,
+            defaultHighInputLatency: 0.0,
+            defaultHighOutputLatency: 0.1,
+            defaultSampleRate: 44_100.0,
+        });
+        let alsa = info.stable_id(HostApiTypeId::ALSA);
+        assert_eq!(alsa, info.clone().stable_id(HostApiTypeId::ALSA));
+        assert_ne!(alsa, info.stable_id(HostApiTypeId::JACK));
+    }
+
+    #[test]
+    fn usb_ids_are_parsed_from_alsa_cards() {
+        assert_eq!(alsa_card_number("USB Audio CODEC: - (hw:12,0)"), Some(12));
+        assert_eq!(alsa_card_number("default"), None);
+        assert_eq!(parse_alsa_usbid("046d:0a44\n"), Some((0x046d, 0x0a44)));
+        assert_eq!(parse_alsa_usbid("046d"), None);
+    }
+}
@@ -0,0 +1,28 @@
+//! Helpers for working with buffers of [**Sample**](../trait.Sample.html)s.
+
+use super::Sample;
+
+/// Fill the given `buffer` with silence, i.e. the `Sample::SILENCE` of its sample type.
+///
+/// Unlike filling the buffer with `0` (or `Default::default()`), this produces silence for
+/// unsigned formats too, where `0` is the most negative sample rather than the midpoint.
+pub fn fill_silence<T: Sample>(buffer: &mut [T]) {
+    for sample in buffer.iter_mut() {
+        *sample = T::SILENCE;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u8_silence_is_the_midpoint() {
+        assert_eq!(u8::SILENCE, 128);
+        assert_eq!(i16::SILENCE, 0);
+        assert_eq!(f32::SILENCE, 0.0);
+        let mut buffer = [0u8; 4];
+        fill_silence(&mut buffer);
+        assert_eq!(buffer, [128; 4]);
+    }
+}
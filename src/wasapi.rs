@@ -0,0 +1,207 @@
+//! Queries of the Windows multimedia device (MMDevice) API that PortAudio does not expose.
+//!
+//! PortAudio's WASAPI host API only reports the default endpoint for the multimedia role, so the
+//! default endpoints of the other roles are looked up directly via COM.
+
+use std::os::raw::c_void;
+use std::{mem, ptr};
+
+use types::{Direction, Role};
+
+#[repr(C)]
+struct Guid {
+    data1: u32,
+    data2: u16,
+    data3: u16,
+    data4: [u8; 8],
+}
+
+#[repr(C)]
+struct PropertyKey {
+    fmtid: Guid,
+    pid: u32,
+}
+
+/// The leading fields of a `PROPVARIANT`, padded to its full size.
+#[repr(C)]
+struct PropVariant {
+    vt: u16,
+    reserved: [u16; 3],
+    data: [usize; 2],
+}
+
+/// A COM object, laid out as a pointer to its table of methods.
+#[repr(C)]
+struct Object<V> {
+    vtbl: *const V,
+}
+
+/// The leading methods of `IMMDeviceEnumerator`. Unused methods are only given as placeholders.
+#[repr(C)]
+struct DeviceEnumeratorVtbl {
+    query_interface: usize,
+    add_ref: usize,
+    release: unsafe extern "system" fn(*mut Object<DeviceEnumeratorVtbl>) -> u32,
+    enum_audio_endpoints: usize,
+    get_default_audio_endpoint: unsafe extern "system" fn(
+        *mut Object<DeviceEnumeratorVtbl>,
+        u32,
+        u32,
+        *mut *mut Object<DeviceVtbl>,
+    ) -> i32,
+}
+
+/// The leading methods of `IMMDevice`.
+#[repr(C)]
+struct DeviceVtbl {
+    query_interface: usize,
+    add_ref: usize,
+    release: unsafe extern "system" fn(*mut Object<DeviceVtbl>) -> u32,
+    activate: usize,
+    open_property_store: unsafe extern "system" fn(
+        *mut Object<DeviceVtbl>,
+        u32,
+        *mut *mut Object<PropertyStoreVtbl>,
+    ) -> i32,
+}
+
+/// The leading methods of `IPropertyStore`.
+#[repr(C)]
+struct PropertyStoreVtbl {
+    query_interface: usize,
+    add_ref: usize,
+    release: unsafe extern "system" fn(*mut Object<PropertyStoreVtbl>) -> u32,
+    get_count: usize,
+    get_at: usize,
+    get_value: unsafe extern "system" fn(
+        *mut Object<PropertyStoreVtbl>,
+        *const PropertyKey,
+        *mut PropVariant,
+    ) -> i32,
+}
+
+#[link(name = "ole32")]
+extern "system" {
+    fn CoInitializeEx(reserved: *mut c_void, co_init: u32) -> i32;
+    fn CoUninitialize();
+    fn CoCreateInstance(
+        clsid: *const Guid,
+        outer: *mut c_void,
+        cls_context: u32,
+        iid: *const Guid,
+        object: *mut *mut c_void,
+    ) -> i32;
+    fn PropVariantClear(value: *mut PropVariant) -> i32;
+}
+
+const COINIT_MULTITHREADED: u32 = 0;
+const CLSCTX_ALL: u32 = 0x17;
+const STGM_READ: u32 = 0;
+const VT_LPWSTR: u16 = 31;
+
+const E_RENDER: u32 = 0;
+const E_CAPTURE: u32 = 1;
+const E_MULTIMEDIA: u32 = 1;
+const E_COMMUNICATIONS: u32 = 2;
+
+const CLSID_MM_DEVICE_ENUMERATOR: Guid = Guid {
+    data1: 0xBCDE_0395,
+    data2: 0xE52F,
+    data3: 0x467C,
+    data4: [0x8E, 0x3D, 0xC4, 0x57, 0x92, 0x91, 0x69, 0x2E],
+};
+
+const IID_IMM_DEVICE_ENUMERATOR: Guid = Guid {
+    data1: 0xA956_64D2,
+    data2: 0x9614,
+    data3: 0x4F35,
+    data4: [0xA7, 0x46, 0xDE, 0x8D, 0xB6, 0x36, 0x17, 0xE6],
+};
+
+const PKEY_DEVICE_FRIENDLY_NAME: PropertyKey = PropertyKey {
+    fmtid: Guid {
+        data1: 0xA45C_254E,
+        data2: 0xDF1C,
+        data3: 0x4EFD,
+        data4: [0x80, 0x20, 0x67, 0xD1, 0x46, 0xA8, 0x50, 0xE0],
+    },
+    pid: 14,
+};
+
+/// The friendly name of the default endpoint for the given `role` in the given `direction`.
+///
+/// This is the name under which PortAudio's WASAPI host API lists the endpoint. Returns `None` if
+/// there is no such endpoint or it could not be queried.
+pub fn default_endpoint_name(direction: Direction, role: Role) -> Option<String> {
+    unsafe {
+        // Fails if COM was already initialised on this thread with a different concurrency model,
+        // in which case it may still be used but must not be uninitialised here.
+        let initialised = CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED) >= 0;
+        let name = endpoint_name(direction, role);
+        if initialised {
+            CoUninitialize();
+        }
+        name
+    }
+}
+
+unsafe fn endpoint_name(direction: Direction, role: Role) -> Option<String> {
+    let mut enumerator: *mut Object<DeviceEnumeratorVtbl> = ptr::null_mut();
+    let result = CoCreateInstance(
+        &CLSID_MM_DEVICE_ENUMERATOR,
+        ptr::null_mut(),
+        CLSCTX_ALL,
+        &IID_IMM_DEVICE_ENUMERATOR,
+        &mut enumerator as *mut _ as *mut *mut c_void,
+    );
+    if result < 0 || enumerator.is_null() {
+        return None;
+    }
+
+    let data_flow = match direction {
+        Direction::Input => E_CAPTURE,
+        Direction::Output => E_RENDER,
+    };
+    let role = match role {
+        Role::Communications => E_COMMUNICATIONS,
+        Role::Multimedia => E_MULTIMEDIA,
+    };
+    let mut device: *mut Object<DeviceVtbl> = ptr::null_mut();
+    let vtbl = &*(*enumerator).vtbl;
+    let result = (vtbl.get_default_audio_endpoint)(enumerator, data_flow, role, &mut device);
+    (vtbl.release)(enumerator);
+    if result < 0 || device.is_null() {
+        return None;
+    }
+
+    let mut store: *mut Object<PropertyStoreVtbl> = ptr::null_mut();
+    let vtbl = &*(*device).vtbl;
+    let result = (vtbl.open_property_store)(device, STGM_READ, &mut store);
+    (vtbl.release)(device);
+    if result < 0 || store.is_null() {
+        return None;
+    }
+
+    let mut value: PropVariant = mem::zeroed();
+    let vtbl = &*(*store).vtbl;
+    let result = (vtbl.get_value)(store, &PKEY_DEVICE_FRIENDLY_NAME, &mut value);
+    (vtbl.release)(store);
+    if result < 0 {
+        return None;
+    }
+
+    let chars = value.data[0] as *const u16;
+    let name = if value.vt == VT_LPWSTR && !chars.is_null() {
+        let mut len = 0;
+        while *chars.add(len) != 0 {
+            len += 1;
+        }
+        Some(String::from_utf16_lossy(::std::slice::from_raw_parts(
+            chars, len,
+        )))
+    } else {
+        None
+    };
+    PropVariantClear(&mut value);
+    name
+}
@@ -0,0 +1,12 @@
+// A **Blocking** stream has no callback, so the methods controlling a callback (such as
+// `request_stop`, `pause` and `on_callback_panic`) are not available.
+
+extern crate portaudio;
+
+use portaudio::{Blocking, Output, Stream};
+
+fn request_stop(stream: &Stream<Blocking<portaudio::stream::Buffer>, Output<f32>>) {
+    stream.request_stop();
+}
+
+fn main() {}
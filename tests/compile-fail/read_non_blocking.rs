@@ -0,0 +1,12 @@
+// Reading from a callback stream is rejected with `Error::CanNotReadFromACallbackStream` by
+// PortAudio, so a **NonBlocking** stream has no `read` method.
+
+extern crate portaudio;
+
+use portaudio::{Input, NonBlocking, Stream};
+
+fn read(stream: &Stream<NonBlocking, Input<f32>>) {
+    let _ = stream.read(256);
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+// Writing to a callback stream is rejected with `Error::CanNotWriteToACallbackStream` by
+// PortAudio, so a **NonBlocking** stream has no `write` method.
+
+extern crate portaudio;
+
+use portaudio::{NonBlocking, Output, Stream};
+
+fn write(stream: &mut Stream<NonBlocking, Output<f32>>) {
+    let _ = stream.write(256, |buffer| {
+        for sample in buffer.iter_mut() {
+            *sample = 0.0;
+        }
+    });
+}
+
+fn main() {}
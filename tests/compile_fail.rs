@@ -0,0 +1,10 @@
+//! Checks that misusing a **Stream** in a way that PortAudio would reject at runtime is instead
+//! rejected at compile time.
+
+extern crate trybuild;
+
+#[test]
+fn stream_modes_are_separate() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/compile-fail/*.rs");
+}
@@ -0,0 +1,109 @@
+//! Checks that reading from and writing to a stream opened via
+//! **PortAudio::open_with_format_fallback** does not allocate once its conversion buffers have
+//! grown to the size of a block.
+//!
+//! The tests require a default input and output device that accept one of the tried formats at
+//! 44.1kHz, and pass trivially without them.
+
+extern crate portaudio;
+
+use portaudio as pa;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts the allocations made by the current thread so that tests running in parallel don't
+/// interfere with one another.
+struct CountingAllocator;
+
+thread_local!(static ALLOCATIONS: Cell<usize> = const { Cell::new(0) });
+
+fn count_allocation() {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const SAMPLE_RATE: f64 = 44_100.0;
+const CHANNELS: i32 = 2;
+const FRAMES: u32 = 256;
+/// The formats tried when opening, none of which match the `f32` samples read and written.
+const FORMATS: &[pa::SampleFormat] = &[pa::SampleFormat::I16, pa::SampleFormat::I32];
+
+#[test]
+fn steady_state_read_does_not_allocate() {
+    let pa = pa::PortAudio::new().unwrap();
+    let device = match pa.default_input_device() {
+        Ok(device) => device,
+        Err(_) => return,
+    };
+    let params = pa::StreamParameters::<f32>::new(device, CHANNELS, true, 0.1);
+    let settings = pa::InputStreamSettings::new(params, SAMPLE_RATE, FRAMES);
+    let mut stream = match pa.open_with_format_fallback(settings, FORMATS) {
+        Ok((stream, _)) => stream,
+        Err(_) => return,
+    };
+    stream.start().unwrap();
+
+    // Warm up with the largest block.
+    assert_eq!(stream.read(FRAMES).unwrap().len(), FRAMES as usize * 2);
+
+    let before = allocations();
+    for frames in (FRAMES - 100..FRAMES).rev() {
+        assert_eq!(stream.read(frames).unwrap().len(), frames as usize * 2);
+    }
+    assert_eq!(allocations(), before);
+    stream.stop().unwrap();
+}
+
+#[test]
+fn steady_state_write_does_not_allocate() {
+    let pa = pa::PortAudio::new().unwrap();
+    let device = match pa.default_output_device() {
+        Ok(device) => device,
+        Err(_) => return,
+    };
+    let params = pa::StreamParameters::<f32>::new(device, CHANNELS, true, 0.1);
+    let settings = pa::OutputStreamSettings::new(params, SAMPLE_RATE, FRAMES);
+    let mut stream = match pa.open_with_format_fallback(settings, FORMATS) {
+        Ok((stream, _)) => stream,
+        Err(_) => return,
+    };
+    stream.start().unwrap();
+
+    // Warm up with the largest block.
+    stream
+        .write(FRAMES, |buffer| {
+            assert_eq!(buffer.len(), FRAMES as usize * 2)
+        })
+        .unwrap();
+
+    let before = allocations();
+    for frames in (FRAMES - 100..FRAMES).rev() {
+        stream
+            .write(frames, |buffer| buffer.iter_mut().for_each(|s| *s = 0.5))
+            .unwrap();
+    }
+    assert_eq!(allocations(), before);
+    stream.stop().unwrap();
+}